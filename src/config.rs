@@ -0,0 +1,173 @@
+//! The configuration file. Every setting in it is optional and can be overridden by the
+//! corresponding command line flag.
+
+use crate::mapping::UserOptions;
+use crate::template::Filenames;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    IOError(io::Error),
+    TOMLError(toml::de::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IOError(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::TOMLError(err)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The target directory of the mount.
+    pub path: Option<PathBuf>,
+    pub login: Option<Login>,
+    pub mpeg_padding: Option<bool>,
+    /// The maximum number of results listed per kind of a search.
+    pub search_limit: Option<usize>,
+    pub id3: Id3Config,
+    pub cache: CacheConfig,
+    /// The templates for the names of tracks.
+    pub filenames: Filenames,
+    /// The users to create directory and file entries for.
+    #[serde(rename = "user")]
+    pub users: Vec<UserOptions>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Id3Config {
+    pub images: Option<bool>,
+    pub parse_strings: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Seconds.
+    pub ttl: Option<u64>,
+    /// Seconds.
+    pub negative_ttl: Option<u64>,
+    pub refresh_background: Option<bool>,
+    pub dir: Option<PathBuf>,
+    pub metadata: Option<bool>,
+    /// MiB.
+    pub audio_size: Option<u64>,
+    pub persistent_inodes: Option<bool>,
+}
+
+impl Config {
+    pub fn open(path: impl AsRef<Path>) -> Result<Config, Error> {
+        let buf = fs::read_to_string(path)?;
+        Ok(toml::from_str(&buf)?)
+    }
+
+    /// Returns the location of the configuration file that is loaded if no other file is
+    /// specified, following the XDG base directory specification.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(base.join("soundcloud-fs").join("config.toml"))
+    }
+
+    /// Returns the options for the specified user, or the defaults if the user is not listed.
+    pub fn user(&self, name: &str) -> UserOptions {
+        self.users
+            .iter()
+            .find(|u| u.name == name)
+            .cloned()
+            .unwrap_or_else(|| UserOptions::new(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config: Config = toml::from_str(
+            r#"
+            path = "/mnt/soundcloud"
+            mpeg_padding = false
+
+            [login]
+            username = "alice"
+            password = "hunter2"
+
+            [cache]
+            ttl = 600
+            metadata = true
+
+            [filenames]
+            tracks = "{artist} - {title}.mp3"
+
+            [[user]]
+            name = "alice"
+            favorites = false
+            depth = 2
+
+            [[user]]
+            name = "bob"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("/mnt/soundcloud")), config.path);
+        assert_eq!("hunter2", config.login.unwrap().password);
+        assert_eq!(Some(600), config.cache.ttl);
+        assert_eq!(None, config.cache.negative_ttl);
+        assert_eq!(None, config.id3.images);
+        assert!(!config.filenames.tracks.is_resolvable());
+        assert!(config.filenames.favorites.is_resolvable());
+
+        let alice = &config.users[0];
+        assert!(!alice.favorites);
+        assert!(alice.following);
+        assert!(!alice.followers);
+        assert_eq!(2, alice.depth);
+        assert_eq!(UserOptions::new("bob"), config.users[1]);
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(toml::from_str::<Config>("[cache]\nttl = 1\nsize = 2\n").is_err());
+        assert!(toml::from_str::<Config>("[[user]]\nfavourites = true\n").is_err());
+        assert!(toml::from_str::<Config>("[filenames]\ntracks = \"{nope}\"\n").is_err());
+    }
+
+    #[test]
+    fn reserved_user() {
+        assert!(toml::from_str::<Config>("[[user]]\nname = \"me\"\n").is_err());
+        assert!(toml::from_str::<Config>("[[user]]\nname = \"resolve\"\n").is_err());
+        assert!(toml::from_str::<Config>("[[user]]\nname = \"meg\"\n").is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+//! Drives the request handlers of `FS` through a plain Rust API, so filesystems can be tested
+//! without mounting them.
+
+use super::*;
+use std::path::{Component, Path};
+
+/// Harness performs the same operations as the kernel would through FUSE, addressing nodes by
+/// path. Failures are reported as errno values.
+pub struct Harness<N>
+where
+    N: NodeType,
+{
+    inner: Inner<N>,
+}
+
+/// A directory entry as it would be returned by readdir.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: fuse::FileType,
+}
+
+impl<N> Harness<N>
+where
+    N: NodeType,
+{
+    pub fn new(root: &N) -> Self {
+        Harness {
+            inner: Inner::new(root, InodeTable::new(), 0, 0),
+        }
+    }
+
+    /// Resolves a path relative to the root to an inode by looking up each component.
+    pub fn lookup(&self, path: impl AsRef<Path>) -> Result<fuse::FileAttr, c_int> {
+        let mut attrs = self.inner.getattr(INO_ROOT)?;
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => {
+                    attrs = self.inner.lookup(attrs.ino, &name.to_string_lossy())?;
+                }
+                Component::RootDir | Component::CurDir => (),
+                _ => return Err(libc::EINVAL),
+            }
+        }
+        Ok(attrs)
+    }
+
+    /// Tells the filesystem that the kernel dropped the specified number of lookups of an inode.
+    pub fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup);
+    }
+
+    /// Returns the number of inodes that are kept in memory.
+    pub fn num_inodes(&self) -> usize {
+        self.inner.nodes.read().unwrap().len()
+    }
+
+    pub fn statfs(&self) -> Stats {
+        self.inner.statfs()
+    }
+
+    pub fn getattr(&self, path: impl AsRef<Path>) -> Result<fuse::FileAttr, c_int> {
+        let ino = self.lookup(path)?.ino;
+        self.inner.getattr(ino)
+    }
+
+    /// Like getattr, but by inode so no lookups are performed.
+    pub fn getattr_ino(&self, ino: u64) -> Result<fuse::FileAttr, c_int> {
+        self.inner.getattr(ino)
+    }
+
+    pub fn getxattr(&self, path: impl AsRef<Path>, name: &str) -> Result<Vec<u8>, c_int> {
+        let ino = self.lookup(path)?.ino;
+        self.inner.getxattr(ino, name.as_ref())
+    }
+
+    /// Returns the names of the extended attributes of a node.
+    pub fn listxattr(&self, path: impl AsRef<Path>) -> Result<Vec<String>, c_int> {
+        let ino = self.lookup(path)?.ino;
+        let names = self.inner.listxattr(ino)?;
+        Ok(names
+            .split(|b| *b == 0)
+            .filter(|n| !n.is_empty())
+            .map(|n| String::from_utf8_lossy(n).into_owned())
+            .collect())
+    }
+
+    /// Lists a directory. The entries are fetched `batch` at a time to exercise readdir offsets.
+    pub fn list_batched(&self, path: impl AsRef<Path>, batch: usize) -> Result<Vec<Entry>, c_int> {
+        let ino = self.lookup(path)?.ino;
+        let fh = self.inner.opendir(ino, 0)?;
+        let mut list = Vec::new();
+        let mut offset = 0;
+        loop {
+            let entries = self.inner.readdir(ino, fh, offset)?;
+            if entries.is_empty() {
+                break;
+            }
+            for (next_offset, name, kind, ino) in entries.into_iter().take(batch) {
+                list.push(Entry { name, ino, kind });
+                offset = next_offset;
+            }
+        }
+        self.inner.releasedir(fh);
+        Ok(list)
+    }
+
+    pub fn list(&self, path: impl AsRef<Path>) -> Result<Vec<Entry>, c_int> {
+        self.list_batched(path, 64)
+    }
+
+    pub fn readlink(&self, path: impl AsRef<Path>) -> Result<PathBuf, c_int> {
+        let ino = self.lookup(path)?.ino;
+        self.inner.readlink(ino)
+    }
+
+    /// Creates a file and returns its attributes. The handle that is opened is released again.
+    pub fn create(&self, path: impl AsRef<Path>) -> Result<fuse::FileAttr, c_int> {
+        let (parent, name) = self.parent(path.as_ref())?;
+        let (attrs, fh) = self.inner.create(parent, &name)?;
+        self.inner.release(fh);
+        Ok(attrs)
+    }
+
+    /// Adds the node at `src` to the directory of `dst` under the name of `dst`.
+    pub fn link(
+        &self,
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+    ) -> Result<fuse::FileAttr, c_int> {
+        let ino = self.lookup(src)?.ino;
+        let (parent, name) = self.parent(dst.as_ref())?;
+        self.inner.link(ino, parent, &name)
+    }
+
+    pub fn mkdir(&self, path: impl AsRef<Path>) -> Result<fuse::FileAttr, c_int> {
+        let (parent, name) = self.parent(path.as_ref())?;
+        self.inner.mkdir(parent, &name)
+    }
+
+    pub fn symlink(
+        &self,
+        target: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Result<fuse::FileAttr, c_int> {
+        let (parent, name) = self.parent(path.as_ref())?;
+        self.inner.symlink(parent, &name, target.as_ref())
+    }
+
+    pub fn unlink(&self, path: impl AsRef<Path>) -> Result<(), c_int> {
+        let (parent, name) = self.parent(path.as_ref())?;
+        self.inner.unlink(parent, &name)
+    }
+
+    /// Looks up the parent directory of a path and returns its inode and the final component.
+    fn parent(&self, path: &Path) -> Result<(u64, String), c_int> {
+        let name = path.file_name().ok_or(libc::EINVAL)?;
+        let parent = self.lookup(path.parent().unwrap_or_else(|| Path::new("")))?;
+        Ok((parent.ino, name.to_string_lossy().into_owned()))
+    }
+
+    /// Opens a file for reading and returns the file handle.
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<(u64, u64), c_int> {
+        let ino = self.lookup(path)?.ino;
+        Ok((ino, self.open_ino(ino)?))
+    }
+
+    pub fn open_ino(&self, ino: u64) -> Result<u64, c_int> {
+        self.inner.open(ino, libc::O_RDONLY as u32)
+    }
+
+    /// Reads from a handle returned by open.
+    pub fn read_handle(&self, ino: u64, fh: u64, offset: u64, size: u32) -> Result<Vec<u8>, c_int> {
+        self.inner.read(ino, fh, offset as i64, size)
+    }
+
+    pub fn release(&self, fh: u64) {
+        self.inner.release(fh);
+    }
+
+    /// Reads a range of a file using a fresh handle.
+    pub fn read(&self, path: impl AsRef<Path>, offset: u64, size: u32) -> Result<Vec<u8>, c_int> {
+        let (ino, fh) = self.open(path)?;
+        let rs = self.read_handle(ino, fh, offset, size);
+        self.release(fh);
+        rs
+    }
+
+    /// Reads a whole file in chunks, like a program copying the file would.
+    pub fn read_all(&self, path: impl AsRef<Path>) -> Result<Vec<u8>, c_int> {
+        const CHUNK_SIZE: u32 = 128 * 1024;
+        let (ino, fh) = self.open(path)?;
+        let mut buf = Vec::new();
+        let rs = loop {
+            match self.read_handle(ino, fh, buf.len() as u64, CHUNK_SIZE) {
+                Ok(ref b) if b.is_empty() => break Ok(buf),
+                Ok(b) => buf.extend(b),
+                Err(errno) => break Err(errno),
+            }
+        };
+        self.release(fh);
+        rs
+    }
+}
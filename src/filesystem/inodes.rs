@@ -0,0 +1,215 @@
+use crate::ioutil::fnv1a;
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// InodeTable assigns inode numbers to nodes.
+///
+/// Inode numbers are derived from a hash of a key that identifies the node, so the same node gets
+/// the same inode number across mounts. When two keys hash to the same number, the key that comes
+/// last is given the next free number instead. Because this depends on the order in which nodes
+/// are encountered, the table can be persisted to a file so such numbers are stable as well.
+///
+/// Without a file, the numbers of forgotten inodes are dropped, so the table only holds the
+/// inodes the kernel knows about. A persistent table keeps every number it has allocated.
+pub struct InodeTable {
+    state: Mutex<State>,
+}
+
+struct State {
+    by_key: HashMap<String, u64>,
+    used: HashMap<u64, String>,
+    // New allocations are appended to this file, if set.
+    file: Option<fs::File>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    ino: u64,
+    key: String,
+}
+
+impl InodeTable {
+    pub fn new() -> Self {
+        InodeTable {
+            state: Mutex::new(State {
+                by_key: HashMap::new(),
+                used: HashMap::new(),
+                file: None,
+            }),
+        }
+    }
+
+    /// Opens a persistent table, creating the file if it does not exist yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut by_key = HashMap::new();
+        let mut used = HashMap::new();
+        for line in io::BufReader::new(&file).lines() {
+            let entry: Entry = match serde_json::from_str(&line?) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!(
+                        "ignoring corrupt inode table entry in {}: {}",
+                        path.display(),
+                        err
+                    );
+                    continue;
+                }
+            };
+            if entry.ino <= INO_RESERVED || used.contains_key(&entry.ino) {
+                warn!(
+                    "ignoring duplicate inode {} in {}",
+                    entry.ino,
+                    path.display()
+                );
+                continue;
+            }
+            used.insert(entry.ino, entry.key.clone());
+            by_key.insert(entry.key, entry.ino);
+        }
+        debug!("loaded {} inodes from {}", by_key.len(), path.display());
+
+        Ok(InodeTable {
+            state: Mutex::new(State {
+                by_key,
+                used,
+                file: Some(file),
+            }),
+        })
+    }
+
+    /// Returns the inode number for the node identified by the key, allocating one if the key was
+    /// not seen before.
+    pub fn inode(&self, key: &str) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        if let Some(ino) = state.by_key.get(key) {
+            return *ino;
+        }
+
+        let ino = state.free_inode(key);
+        state.used.insert(ino, key.to_string());
+        state.by_key.insert(key.to_string(), ino);
+
+        if let Some(file) = &mut state.file {
+            let entry = Entry {
+                ino,
+                key: key.to_string(),
+            };
+            let mut line = serde_json::to_vec(&entry).unwrap();
+            line.push(b'\n');
+            if let Err(err) = file.write_all(&line) {
+                error!("could not persist inode {} for {}: {}", ino, key, err);
+            }
+        }
+        ino
+    }
+
+    /// Returns the inode number the key has or would be allocated, without allocating it. This
+    /// is used for directory listings, which the kernel does not forget.
+    pub fn peek(&self, key: &str) -> u64 {
+        let state = self.state.lock().unwrap();
+        match state.by_key.get(key) {
+            Some(ino) => *ino,
+            None => state.free_inode(key),
+        }
+    }
+
+    /// Releases the number of an inode the kernel has forgotten, unless the table is persistent.
+    pub fn forget(&self, ino: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.file.is_some() {
+            return;
+        }
+        if let Some(key) = state.used.remove(&ino) {
+            state.by_key.remove(&key);
+        }
+    }
+}
+
+impl State {
+    fn free_inode(&self, key: &str) -> u64 {
+        let mut ino = fnv1a(key.as_bytes());
+        while ino <= INO_RESERVED || self.used.contains_key(&ino) {
+            if let Some(other) = self.used.get(&ino) {
+                debug!("inode {} of {} collides with {}", ino, key, other);
+            }
+            ino = ino.wrapping_add(1);
+        }
+        ino
+    }
+}
+
+// Inode 0 is invalid and 1 belongs to the root.
+const INO_RESERVED: u64 = 1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn stable() {
+        let a = InodeTable::new();
+        let b = InodeTable::new();
+        assert_eq!(a.inode("track:1"), b.inode("track:1"));
+        assert_eq!(a.inode("track:1"), a.inode("track:1"));
+        assert_ne!(a.inode("track:1"), a.inode("track:2"));
+        assert_eq!(fnv1a(b"track:1"), a.inode("track:1"));
+    }
+
+    #[test]
+    fn forget() {
+        let table = InodeTable::new();
+        let ino = table.inode("track:1");
+        assert_eq!(ino, table.peek("track:1"));
+        assert_eq!(fnv1a(b"track:2"), table.peek("track:2"));
+        assert_eq!(1, table.state.lock().unwrap().used.len());
+
+        table.forget(ino);
+        assert!(table.state.lock().unwrap().used.is_empty());
+        assert!(table.state.lock().unwrap().by_key.is_empty());
+        assert_eq!(ino, table.inode("track:1"));
+    }
+
+    #[test]
+    fn persisted_collision() {
+        let path = env::temp_dir().join(format!("soundcloud-fs-inodes-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // Make another key claim the number that would be derived from "track:1".
+        let hash = fnv1a(b"track:1");
+        let entry = serde_json::to_string(&Entry {
+            ino: hash,
+            key: "track:2".to_string(),
+        })
+        .unwrap();
+        fs::write(&path, format!("{}\nnot json\n", entry)).unwrap();
+
+        let table = InodeTable::open(&path).unwrap();
+        assert_eq!(hash, table.inode("track:2"));
+        let ino = table.inode("track:1");
+        assert_ne!(hash, ino);
+        // Persisted numbers are kept after the inode is forgotten.
+        table.forget(hash);
+        assert!(table.state.lock().unwrap().used.contains_key(&hash));
+        drop(table);
+
+        let table = InodeTable::open(&path).unwrap();
+        assert_eq!(ino, table.inode("track:1"));
+        assert_eq!(hash, table.inode("track:2"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,57 +1,107 @@
+#[cfg(test)]
+pub mod harness;
+mod inodes;
 mod node;
 mod nodecache;
+mod workers;
 
+use self::workers::WorkerPool;
 use chrono::{DateTime, Utc};
 use fuse;
 use log::*;
-use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::ffi;
-use std::hash::{Hash, Hasher};
 use std::io::{self, Read, Seek};
 use std::os;
+use std::os::raw::c_int;
 use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
+pub use self::inodes::InodeTable;
 pub use self::node::*;
 pub use self::node::{Metadata, NodeType};
 pub use self::nodecache::*;
 pub use crate::ioutil::*;
 
 const INO_ROOT: u64 = 1;
+const BLOCK_SIZE: u64 = 1024;
+const MAX_NAME_LEN: u32 = 255;
 
+/// Inode tracks a node that is known to the kernel.
+///
+/// The inode is dropped when the kernel forgets about it, after which the kernel will not refer to
+/// it again until it is returned by another lookup.
+struct Inode<N>
+where
+    N: NodeType,
+{
+    node: Node<N>,
+    // The number of times the inode was returned by lookup minus the number of times it was
+    // forgotten.
+    lookups: u64,
+    // The size of the node as last reported to the kernel.
+    size: u64,
+}
+
+/// Statistics of the filesystem as reported by statfs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// The total size of the files that are known to the kernel in blocks of BLOCK_SIZE.
+    pub blocks: u64,
+    /// The number of inodes that are known to the kernel.
+    pub files: u64,
+}
+
+/// The name, node and inode of each entry in an open directory.
+type DirEntries<N> = Arc<Vec<(String, Node<N>, u64)>>;
+
+/// FS implements the FUSE API for a tree of nodes.
+///
+/// Requests are handled concurrently by a pool of worker threads, so a slow operation on one node
+/// does not block operations on other nodes.
 pub struct FS<N>
 where
     N: NodeType,
 {
-    nodes: HashMap<u64, Node<N>>,
+    inner: Arc<Inner<N>>,
+    workers: WorkerPool,
+}
+
+struct Inner<N>
+where
+    N: NodeType,
+{
+    nodes: RwLock<HashMap<u64, Inode<N>>>,
+    inodes: InodeTable,
 
-    read_handles: HashMap<u64, <N::File as File>::Reader>,
-    next_read_handle: u64,
+    read_handles: Mutex<HashMap<u64, Arc<Mutex<<N::File as File>::Reader>>>>,
+    next_read_handle: AtomicU64,
 
-    readdir_handles: HashMap<u64, Vec<(String, Node<N>, u64)>>,
-    next_readdir_handle: u64,
+    readdir_handles: Mutex<HashMap<u64, DirEntries<N>>>,
+    next_readdir_handle: AtomicU64,
 
     uid: u32,
     gid: u32,
 }
 
-impl<'a, N> FS<N>
+impl<N> FS<N>
 where
     N: NodeType,
 {
-    pub fn new(root: &N, uid: u32, gid: u32) -> Self {
-        let mut nodes = HashMap::new();
-        nodes.insert(INO_ROOT, Node::Directory(root.root()));
+    pub fn new(root: &N, inodes: InodeTable, uid: u32, gid: u32, num_threads: usize) -> Self {
         FS {
-            nodes,
-            read_handles: HashMap::new(),
-            next_read_handle: 1,
-            readdir_handles: HashMap::new(),
-            next_readdir_handle: 1,
-            uid,
-            gid,
+            inner: Arc::new(Inner::new(root, inodes, uid, gid)),
+            workers: WorkerPool::new(num_threads),
         }
     }
+
+    /// Runs the specified request handler on one of the worker threads.
+    fn dispatch(&self, f: impl FnOnce(&Inner<N>) + Send + 'static) {
+        let inner = self.inner.clone();
+        self.workers.execute(move || f(&inner));
+    }
 }
 
 impl<N> fuse::Filesystem for FS<N>
@@ -74,122 +124,35 @@ where
         os_name: &ffi::OsStr,
         reply: fuse::ReplyEntry,
     ) {
-        let name = os_name.to_string_lossy();
-        trace!("fuse lookup, {}, {}", parent_ino, name);
-
-        let child = {
-            let parent = match self.nodes.get(&parent_ino) {
-                Some(v) => v,
-                None => {
-                    error!("fuse: no node for inode {}", parent_ino);
-                    reply.error(libc::ENOENT);
-                    return;
-                }
-            };
-            let dir = parent
-                .directory()
-                .expect("can not call lookup on a non-directory");
-            match dir.file_by_name(&name) {
-                Ok(v) => v,
-                Err(err) => {
-                    if err.errno() != libc::ENOENT {
-                        error!("fuse: could not get child {}: {}", name, err);
-                    }
-                    reply.error(err.errno());
-                    return;
-                }
-            }
-        };
-
-        let child_ino = inode_for_child(parent_ino, &name);
-
-        let attrs = match attrs_for_file(&child, child_ino, self.uid, self.gid) {
-            Ok(v) => v,
-            Err(err) => {
-                error!("fuse: can not get attrs for {}: {}", child_ino, err);
-                reply.error(err.errno());
-                return;
-            }
-        };
-
-        self.nodes.insert(child_ino, child);
-
-        let now = time::now().to_timespec();
-        reply.entry(&now, &attrs, 0);
+        let name = os_name.to_string_lossy().into_owned();
+        self.dispatch(move |fs| match fs.lookup(parent_ino, &name) {
+            Ok(attrs) => reply.entry(&time::now().to_timespec(), &attrs, 0),
+            Err(errno) => reply.error(errno),
+        });
     }
 
     fn getattr(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyAttr) {
-        trace!("fuse getattr: {}", ino);
-
-        if let Some(node) = self.nodes.get(&ino) {
-            let attrs = match attrs_for_file(&node, ino, self.uid, self.gid) {
-                Ok(v) => v,
-                Err(err) => {
-                    error!("fuse: can not get attrs for {}: {}", ino, err);
-                    reply.error(err.errno());
-                    return;
-                }
-            };
-            let ttl = (time::now() + time::Duration::seconds(30)).to_timespec();
-            reply.attr(&ttl, &attrs);
-        } else {
-            reply.error(libc::ENOENT);
-        }
+        self.dispatch(move |fs| match fs.getattr(ino) {
+            Ok(attrs) => {
+                let ttl = (time::now() + time::Duration::seconds(30)).to_timespec();
+                reply.attr(&ttl, &attrs);
+            }
+            Err(errno) => reply.error(errno),
+        });
     }
 
     fn readlink(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyData) {
-        trace!("fuse readlink: ino={}", ino);
-
-        if let Some(node) = self.nodes.get(&ino) {
-            let symlink = node
-                .symlink()
-                .expect("can not call readlink on a non-symlink");
-            let path = match symlink.read_link() {
-                Ok(v) => v,
-                Err(err) => {
-                    error!("fuse: could not read symlink: {}", err);
-                    reply.error(err.errno());
-                    return;
-                }
-            };
-            reply.data(path.as_os_str().as_bytes());
-        } else {
-            reply.error(libc::ENOENT);
-        }
+        self.dispatch(move |fs| match fs.readlink(ino) {
+            Ok(path) => reply.data(path.as_os_str().as_bytes()),
+            Err(errno) => reply.error(errno),
+        });
     }
 
     fn open(&mut self, _req: &fuse::Request, ino: u64, flags: u32, reply: fuse::ReplyOpen) {
-        trace!("fuse open: {}, {:b}", ino, flags);
-
-        const WRITE_FLAGS: i32 = libc::O_APPEND | libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC;
-        if flags & WRITE_FLAGS as u32 != 0 {
-            error!("fuse: encountered write flag {:b}", flags);
-            reply.error(libc::EROFS);
-            return;
-        }
-
-        let node = match self.nodes.get(&ino) {
-            Some(v) => v,
-            None => {
-                error!("fuse: no such inode: {}", ino);
-                reply.error(libc::ENOENT);
-                return;
-            }
-        };
-        let file = node.file().expect("can not call open on a non-file");
-        let reader = match file.open_ro() {
-            Ok(v) => v,
-            Err(err) => {
-                error!("fuse: could not read inode {}: {}", ino, err);
-                reply.error(libc::EIO);
-                return;
-            }
-        };
-
-        let fh = self.next_read_handle;
-        self.next_read_handle += 1;
-        self.read_handles.insert(fh, reader);
-        reply.opened(fh, flags);
+        self.dispatch(move |fs| match fs.open(ino, flags) {
+            Ok(fh) => reply.opened(fh, flags),
+            Err(errno) => reply.error(errno),
+        });
     }
 
     fn read(
@@ -201,40 +164,10 @@ where
         size: u32,
         reply: fuse::ReplyData,
     ) {
-        trace!(
-            "fuse read: ino={}, fh={}, offset={}, size={}",
-            ino,
-            fh,
-            offset,
-            size
-        );
-
-        let reader = match self.read_handles.get_mut(&fh) {
-            Some(e) => e,
-            None => {
-                error!("fuse: no such open read handle, {}, inode {}", fh, ino);
-                reply.error(libc::EBADF);
-                return;
-            }
-        };
-
-        if let Err(err) = reader.seek(io::SeekFrom::Start(offset as u64)) {
-            error!("fuse: {}", err);
-            reply.error(libc::EIO);
-            return;
-        }
-        trace!("seek to {} ok", offset);
-        let mut buf = vec![0; size as usize];
-        let nread = match reader.read(&mut buf[..]) {
-            Ok(v) => v,
-            Err(err) => {
-                error!("fuse: {}", err);
-                reply.error(libc::EIO);
-                return;
-            }
-        };
-        trace!("read {} bytes ok", nread);
-        reply.data(&buf[..nread]);
+        self.dispatch(move |fs| match fs.read(ino, fh, offset, size) {
+            Ok(buf) => reply.data(&buf),
+            Err(errno) => reply.error(errno),
+        });
     }
 
     fn release(
@@ -256,7 +189,7 @@ where
             flush
         );
 
-        self.read_handles.remove(&fh);
+        self.inner.release(fh);
         reply.ok();
     }
 
@@ -267,44 +200,10 @@ where
         flags: u32,
         reply: fuse::ReplyOpen,
     ) {
-        trace!("fuse opendir: {}, {}", parent_ino, flags);
-
-        let children = {
-            let node = match self.nodes.get(&parent_ino) {
-                Some(v) => v,
-                None => {
-                    error!("fuse: no entry for inode {}", parent_ino);
-                    reply.error(libc::ENOENT);
-                    return;
-                }
-            };
-            let dir = node
-                .directory()
-                .expect("can not call lookup on a non-directory");
-            match dir.files() {
-                Ok(v) => v,
-                Err(err) => {
-                    error!(
-                        "fuse: could not get children for inode {}: {}",
-                        parent_ino, err
-                    );
-                    reply.error(libc::EIO);
-                    return;
-                }
-            }
-        };
-        let entries = children
-            .into_iter()
-            .map(|(name, entry)| {
-                let ino = inode_for_child(parent_ino, &name);
-                (name, entry, ino)
-            })
-            .collect();
-
-        let fh = self.next_readdir_handle;
-        self.next_readdir_handle += 1;
-        self.readdir_handles.insert(fh, entries);
-        reply.opened(fh, flags);
+        self.dispatch(move |fs| match fs.opendir(parent_ino, flags) {
+            Ok(fh) => reply.opened(fh, flags),
+            Err(errno) => reply.error(errno),
+        });
     }
 
     fn readdir(
@@ -315,25 +214,16 @@ where
         offset: i64,
         mut reply: fuse::ReplyDirectory,
     ) {
-        trace!("fuse readdir: {}, {}, {}", parent_ino, fh, offset);
-
-        let entries = match self.readdir_handles.get(&fh) {
-            Some(e) => e,
-            None => {
-                error!(
-                    "fuse: no open readdir handle for handle {}, inode {}",
-                    fh, parent_ino
-                );
-                reply.error(libc::EBADF);
+        let entries = match self.inner.readdir(parent_ino, fh, offset) {
+            Ok(v) => v,
+            Err(errno) => {
+                reply.error(errno);
                 return;
             }
         };
-
-        let iter = entries.iter().skip(offset as usize).enumerate();
-        for (i, (name, node, ino)) in iter {
-            let typ = filetype_for_node(&node);
+        for (next_offset, name, typ, ino) in entries {
             trace!("fuse readdir node: {} {:?}, {}", ino, typ, name);
-            if reply.add(*ino, offset + i as i64 + 1, typ, name) {
+            if reply.add(ino, next_offset, typ, name) {
                 break;
             }
         }
@@ -350,10 +240,16 @@ where
     ) {
         trace!("fuse releasedir: {}, {}, {}", parent_ino, fh, flags);
 
-        self.readdir_handles.remove(&fh);
+        self.inner.releasedir(fh);
         reply.ok();
     }
 
+    fn forget(&mut self, _req: &fuse::Request, ino: u64, nlookup: u64) {
+        // The kernel only forgets inodes after it has received the reply of the lookups, so this
+        // can not race with the lookups that are still running on the workers.
+        self.inner.forget(ino, nlookup);
+    }
+
     fn access(&mut self, _req: &fuse::Request, ino: u64, mask: u32, reply: fuse::ReplyEmpty) {
         trace!("fuse access: {}, {}", ino, mask);
         reply.ok();
@@ -388,96 +284,146 @@ where
     //    ) {
     //        unimplemented!();
     //    }
-    //    fn statfs(&mut self, _req: &fuse::Request, ino: u64, _reply: fuse::ReplyStatfs) {
-    //    }
-    //    fn getxattr(
-    //        &mut self,
-    //        _req: &fuse::Request,
-    //        _ino: u64,
-    //        _os_name: &ffi::OsStr,
-    //        _size: u32,
-    //        reply: fuse::ReplyXattr,
-    //    ) {
-    //        unimplemented!();
-    //    }
-    //
-    //    fn listxattr(&mut self, _req: &fuse::Request, _ino: u64, _size: u32, _reply: fuse::ReplyXattr) {
-    //        unimplemented!();
-    //    }
-    //    fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) { ... }
-    //    fn setattr(
-    //        &mut self,
-    //        _req: &Request,
-    //        _ino: u64,
-    //        _mode: Option<u32>,
-    //        _uid: Option<u32>,
-    //        _gid: Option<u32>,
-    //        _size: Option<u64>,
-    //        _atime: Option<Timespec>,
-    //        _mtime: Option<Timespec>,
-    //        _fh: Option<u64>,
-    //        _crtime: Option<Timespec>,
-    //        _chgtime: Option<Timespec>,
-    //        _bkuptime: Option<Timespec>,
-    //        _flags: Option<u32>,
-    //        reply: ReplyAttr
-    //    ) { ... }
-    //    fn mknod(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _mode: u32,
-    //        _rdev: u32,
-    //        reply: ReplyEntry
-    //    ) { ... }
-    //    fn mkdir(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _mode: u32,
-    //        reply: ReplyEntry
-    //    ) { ... }
-    //    fn unlink(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        reply: ReplyEmpty
-    //    ) { ... }
-    //    fn rmdir(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        reply: ReplyEmpty
-    //    ) { ... }
-    //    fn symlink(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _link: &Path,
-    //        reply: ReplyEntry
-    //    ) { ... }
-    //    fn rename(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _newparent: u64,
-    //        _newname: &OsStr,
-    //        reply: ReplyEmpty
-    //    ) { ... }
-    //    fn link(
-    //        &mut self,
-    //        _req: &Request,
-    //        _ino: u64,
-    //        _newparent: u64,
-    //        _newname: &OsStr,
-    //        reply: ReplyEntry
-    //    ) { ... }
+
+    fn statfs(&mut self, _req: &fuse::Request, _ino: u64, reply: fuse::ReplyStatfs) {
+        let stats = self.inner.statfs();
+        // There is no free space as nothing can be written.
+        reply.statfs(
+            stats.blocks,
+            0,
+            0,
+            stats.files,
+            0,
+            BLOCK_SIZE as u32,
+            MAX_NAME_LEN,
+            BLOCK_SIZE as u32,
+        );
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &fuse::Request,
+        ino: u64,
+        name: &ffi::OsStr,
+        size: u32,
+        reply: fuse::ReplyXattr,
+    ) {
+        let name = name.to_os_string();
+        self.dispatch(move |inner| match inner.getxattr(ino, &name) {
+            Ok(value) => reply_xattr(reply, size, &value),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn listxattr(&mut self, _req: &fuse::Request, ino: u64, size: u32, reply: fuse::ReplyXattr) {
+        self.dispatch(move |inner| match inner.listxattr(ino) {
+            Ok(names) => reply_xattr(reply, size, &names),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn setattr(
+        &mut self,
+        req: &fuse::Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<time::Timespec>,
+        _mtime: Option<time::Timespec>,
+        _fh: Option<u64>,
+        _crtime: Option<time::Timespec>,
+        _chgtime: Option<time::Timespec>,
+        _bkuptime: Option<time::Timespec>,
+        _flags: Option<u32>,
+        reply: fuse::ReplyAttr,
+    ) {
+        // Changing timestamps is accepted but has no effect, so programs like touch work on the
+        // entries they have just created.
+        if mode.is_some() || uid.is_some() || gid.is_some() || size.is_some() {
+            reply.error(libc::EROFS);
+            return;
+        }
+        self.getattr(req, ino, reply);
+    }
+
+    fn unlink(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        reply: fuse::ReplyEmpty,
+    ) {
+        let name = os_name.to_string_lossy().into_owned();
+        self.dispatch(move |fs| match fs.unlink(parent_ino, &name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        _mode: u32,
+        reply: fuse::ReplyEntry,
+    ) {
+        let name = os_name.to_string_lossy().into_owned();
+        self.dispatch(move |fs| match fs.mkdir(parent_ino, &name) {
+            Ok(attrs) => reply.entry(&time::now().to_timespec(), &attrs, 0),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn symlink(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        link: &Path,
+        reply: fuse::ReplyEntry,
+    ) {
+        let name = os_name.to_string_lossy().into_owned();
+        let link = link.to_path_buf();
+        self.dispatch(move |fs| match fs.symlink(parent_ino, &name, &link) {
+            Ok(attrs) => reply.entry(&time::now().to_timespec(), &attrs, 0),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn link(
+        &mut self,
+        _req: &fuse::Request,
+        ino: u64,
+        new_parent_ino: u64,
+        os_name: &ffi::OsStr,
+        reply: fuse::ReplyEntry,
+    ) {
+        let name = os_name.to_string_lossy().into_owned();
+        self.dispatch(move |fs| match fs.link(ino, new_parent_ino, &name) {
+            Ok(attrs) => reply.entry(&time::now().to_timespec(), &attrs, 0),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
+    fn create(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        _mode: u32,
+        flags: u32,
+        reply: fuse::ReplyCreate,
+    ) {
+        let name = os_name.to_string_lossy().into_owned();
+        self.dispatch(move |fs| match fs.create(parent_ino, &name) {
+            Ok((attrs, fh)) => reply.created(&time::now().to_timespec(), &attrs, 0, fh, flags),
+            Err(errno) => reply.error(errno),
+        });
+    }
+
     //    fn write(
     //        &mut self,
     //        _req: &Request,
@@ -529,15 +475,6 @@ where
     //        _name: &OsStr,
     //        reply: ReplyEmpty
     //    ) { ... }
-    //    fn create(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _mode: u32,
-    //        _flags: u32,
-    //        reply: ReplyCreate
-    //    ) { }
     //    fn bmap(
     //        &mut self,
     //        _req: &Request,
@@ -548,11 +485,415 @@ where
     //    ) { }
 }
 
-fn inode_for_child(parent_ino: u64, name: &str) -> u64 {
-    let mut s = DefaultHasher::new();
-    parent_ino.hash(&mut s);
-    name.hash(&mut s);
-    s.finish()
+impl<N> Inner<N>
+where
+    N: NodeType,
+{
+    fn new(root: &N, inodes: InodeTable, uid: u32, gid: u32) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            INO_ROOT,
+            Inode {
+                node: Node::Directory(root.root()),
+                lookups: 1,
+                size: 0,
+            },
+        );
+        Inner {
+            nodes: RwLock::new(nodes),
+            inodes,
+            read_handles: Mutex::new(HashMap::new()),
+            next_read_handle: AtomicU64::new(1),
+            readdir_handles: Mutex::new(HashMap::new()),
+            next_readdir_handle: AtomicU64::new(1),
+            uid,
+            gid,
+        }
+    }
+
+    fn node(&self, ino: u64) -> Result<Node<N>, c_int> {
+        match self.nodes.read().unwrap().get(&ino) {
+            Some(inode) => Ok(inode.node.clone()),
+            None => {
+                error!("fuse: no node for inode {}", ino);
+                Err(libc::ENOENT)
+            }
+        }
+    }
+
+    fn forget(&self, ino: u64, nlookup: u64) {
+        trace!("fuse forget: {}, {}", ino, nlookup);
+
+        if ino == INO_ROOT {
+            return;
+        }
+        let mut nodes = self.nodes.write().unwrap();
+        if let Some(inode) = nodes.get_mut(&ino) {
+            inode.lookups = inode.lookups.saturating_sub(nlookup);
+            if inode.lookups == 0 {
+                nodes.remove(&ino);
+                self.inodes.forget(ino);
+            }
+        }
+    }
+
+    fn lookup(&self, parent_ino: u64, name: &str) -> Result<fuse::FileAttr, c_int> {
+        trace!("fuse lookup, {}, {}", parent_ino, name);
+
+        if name.len() > MAX_NAME_LEN as usize {
+            return Err(libc::ENAMETOOLONG);
+        }
+
+        let child = {
+            let parent = self.node(parent_ino)?;
+            let dir = parent.directory().ok_or(libc::ENOTDIR)?;
+            dir.file_by_name(&name).map_err(|err| {
+                if err.errno() != libc::ENOENT {
+                    error!("fuse: could not get child {}: {}", name, err);
+                }
+                err.errno()
+            })?
+        };
+
+        self.add_lookup(parent_ino, name, child)
+    }
+
+    /// Registers a node that is about to be returned to the kernel as the result of a lookup or
+    /// the creation of an entry.
+    fn add_lookup(
+        &self,
+        parent_ino: u64,
+        name: &str,
+        child: Node<N>,
+    ) -> Result<fuse::FileAttr, c_int> {
+        let child_ino = self.inode_for_child(parent_ino, &name, &child);
+
+        let attrs = attrs_for_file(&child, child_ino, self.uid, self.gid).map_err(|err| {
+            error!("fuse: can not get attrs for {}: {}", child_ino, err);
+            err.errno()
+        })?;
+
+        let mut nodes = self.nodes.write().unwrap();
+        let inode = nodes.entry(child_ino).or_insert_with(|| Inode {
+            node: child.clone(),
+            lookups: 0,
+            size: 0,
+        });
+        inode.node = child;
+        inode.lookups += 1;
+        inode.size = attrs.size;
+        Ok(attrs)
+    }
+
+    fn getattr(&self, ino: u64) -> Result<fuse::FileAttr, c_int> {
+        trace!("fuse getattr: {}", ino);
+
+        let node = self.node(ino)?;
+        let attrs = attrs_for_file(&node, ino, self.uid, self.gid).map_err(|err| {
+            error!("fuse: can not get attrs for {}: {}", ino, err);
+            err.errno()
+        })?;
+        if let Some(inode) = self.nodes.write().unwrap().get_mut(&ino) {
+            inode.size = attrs.size;
+        }
+        Ok(attrs)
+    }
+
+    fn readlink(&self, ino: u64) -> Result<PathBuf, c_int> {
+        trace!("fuse readlink: ino={}", ino);
+
+        let node = self.node(ino)?;
+        let symlink = node.symlink().ok_or(libc::EINVAL)?;
+        symlink.read_link().map_err(|err| {
+            error!("fuse: could not read symlink: {}", err);
+            err.errno()
+        })
+    }
+
+    fn open(&self, ino: u64, flags: u32) -> Result<u64, c_int> {
+        trace!("fuse open: {}, {:b}", ino, flags);
+
+        const WRITE_FLAGS: i32 = libc::O_APPEND | libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC;
+        if flags & WRITE_FLAGS as u32 != 0 {
+            error!("fuse: encountered write flag {:b}", flags);
+            return Err(libc::EROFS);
+        }
+
+        let node = self.node(ino)?;
+        let file = node.file().ok_or(libc::EISDIR)?;
+        let reader = file.open_ro().map_err(|err| {
+            error!("fuse: could not read inode {}: {}", ino, err);
+            err.errno()
+        })?;
+
+        let fh = self.next_read_handle.fetch_add(1, Ordering::SeqCst);
+        self.read_handles
+            .lock()
+            .unwrap()
+            .insert(fh, Arc::new(Mutex::new(reader)));
+        Ok(fh)
+    }
+
+    fn read(&self, ino: u64, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>, c_int> {
+        trace!(
+            "fuse read: ino={}, fh={}, offset={}, size={}",
+            ino,
+            fh,
+            offset,
+            size
+        );
+
+        let reader = match self.read_handles.lock().unwrap().get(&fh) {
+            Some(e) => e.clone(),
+            None => {
+                error!("fuse: no such open read handle, {}, inode {}", fh, ino);
+                return Err(libc::EBADF);
+            }
+        };
+        // Reads on the same handle are serialized, reads on other handles may continue.
+        let mut reader = reader.lock().unwrap();
+
+        if let Err(err) = reader.seek(io::SeekFrom::Start(offset as u64)) {
+            error!("fuse: {}", err);
+            return Err(libc::EIO);
+        }
+        trace!("seek to {} ok", offset);
+        let mut buf = vec![0; size as usize];
+        let nread = reader.read(&mut buf[..]).map_err(|err| {
+            error!("fuse: {}", err);
+            libc::EIO
+        })?;
+        trace!("read {} bytes ok", nread);
+        buf.truncate(nread);
+        Ok(buf)
+    }
+
+    fn release(&self, fh: u64) {
+        self.read_handles.lock().unwrap().remove(&fh);
+    }
+
+    fn opendir(&self, parent_ino: u64, flags: u32) -> Result<u64, c_int> {
+        trace!("fuse opendir: {}, {}", parent_ino, flags);
+
+        let children = {
+            let node = self.node(parent_ino)?;
+            let dir = node.directory().ok_or(libc::ENOTDIR)?;
+            dir.files().map_err(|err| {
+                error!(
+                    "fuse: could not get children for inode {}: {}",
+                    parent_ino, err
+                );
+                err.errno()
+            })?
+        };
+        let entries = children
+            .into_iter()
+            .map(|(name, entry)| {
+                let ino = self.inodes.peek(&inode_key(parent_ino, &name, &entry));
+                (name, entry, ino)
+            })
+            .collect();
+
+        let fh = self.next_readdir_handle.fetch_add(1, Ordering::SeqCst);
+        self.readdir_handles
+            .lock()
+            .unwrap()
+            .insert(fh, Arc::new(entries));
+        Ok(fh)
+    }
+
+    /// Returns the entries of an open directory handle from the offset onwards. Every entry is
+    /// returned with the offset at which to continue after it.
+    fn readdir(
+        &self,
+        parent_ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> Result<Vec<(i64, String, fuse::FileType, u64)>, c_int> {
+        trace!("fuse readdir: {}, {}, {}", parent_ino, fh, offset);
+
+        let entries = match self.readdir_handles.lock().unwrap().get(&fh) {
+            Some(e) => e.clone(),
+            None => {
+                error!(
+                    "fuse: no open readdir handle for handle {}, inode {}",
+                    fh, parent_ino
+                );
+                return Err(libc::EBADF);
+            }
+        };
+        let iter = entries.iter().enumerate().skip(offset.max(0) as usize);
+        Ok(iter
+            .map(|(i, (name, node, ino))| {
+                (i as i64 + 1, name.clone(), filetype_for_node(node), *ino)
+            })
+            .collect())
+    }
+
+    fn releasedir(&self, fh: u64) {
+        self.readdir_handles.lock().unwrap().remove(&fh);
+    }
+
+    /// Computes the statistics from the nodes that are known to the kernel. Sizes are only known
+    /// for files that have been looked up, so the totals grow as more of the tree is visited and
+    /// shrink again as the kernel forgets about nodes.
+    fn statfs(&self) -> Stats {
+        trace!("fuse statfs");
+
+        let nodes = self.nodes.read().unwrap();
+        let size: u64 = nodes.values().map(|inode| inode.size).sum();
+        Stats {
+            blocks: size / BLOCK_SIZE,
+            files: nodes.len() as u64,
+        }
+    }
+
+    /// Returns the directory node of the inode, for operations that modify it.
+    fn directory(&self, ino: u64) -> Result<N::Directory, c_int> {
+        match self.node(ino)? {
+            Node::Directory(dir) => Ok(dir),
+            _ => Err(libc::ENOTDIR),
+        }
+    }
+
+    fn create(&self, parent_ino: u64, name: &str) -> Result<(fuse::FileAttr, u64), c_int> {
+        trace!("fuse create: {}, {}", parent_ino, name);
+
+        let child = self.directory(parent_ino)?.create(name).map_err(|err| {
+            error!("fuse: could not create {}: {}", name, err);
+            err.errno()
+        })?;
+        let attrs = self.add_lookup(parent_ino, name, child)?;
+        // The kernel does not forget the inode if create fails, so the lookup is undone here.
+        match self.open(attrs.ino, libc::O_RDONLY as u32) {
+            Ok(fh) => Ok((attrs, fh)),
+            Err(errno) => {
+                self.forget(attrs.ino, 1);
+                Err(errno)
+            }
+        }
+    }
+
+    fn link(&self, ino: u64, new_parent_ino: u64, name: &str) -> Result<fuse::FileAttr, c_int> {
+        trace!("fuse link: {}, {}, {}", ino, new_parent_ino, name);
+
+        let node = self.node(ino)?;
+        let child = self
+            .directory(new_parent_ino)?
+            .link(name, &node)
+            .map_err(|err| {
+                error!("fuse: could not link {} as {}: {}", ino, name, err);
+                err.errno()
+            })?;
+        self.add_lookup(new_parent_ino, name, child)
+    }
+
+    /// Creates a directory. The parent does not need to list the new directory afterwards, but the
+    /// node it returns for it is registered like the result of a lookup.
+    fn mkdir(&self, parent_ino: u64, name: &str) -> Result<fuse::FileAttr, c_int> {
+        trace!("fuse mkdir: {}, {}", parent_ino, name);
+
+        let child = self.directory(parent_ino)?.mkdir(name).map_err(|err| {
+            error!("fuse: could not create directory {}: {}", name, err);
+            err.errno()
+        })?;
+        self.add_created(parent_ino, name, child, fuse::FileType::Directory)
+    }
+
+    /// Creates a symlink, see mkdir.
+    fn symlink(&self, parent_ino: u64, name: &str, target: &Path) -> Result<fuse::FileAttr, c_int> {
+        trace!("fuse symlink: {}, {}, {:?}", parent_ino, name, target);
+
+        let child = self
+            .directory(parent_ino)?
+            .symlink(name, target)
+            .map_err(|err| {
+                error!("fuse: could not create symlink {}: {}", name, err);
+                err.errno()
+            })?;
+        self.add_created(parent_ino, name, child, fuse::FileType::Symlink)
+    }
+
+    /// Registers the node that was returned for a new entry. The kernel rejects entries that are
+    /// not of the kind that was asked for, for example when a directory is listed as a symlink
+    /// after mkdir. The entry does exist, so EEXIST is returned and the kernel looks it up again.
+    fn add_created(
+        &self,
+        parent_ino: u64,
+        name: &str,
+        child: Node<N>,
+        kind: fuse::FileType,
+    ) -> Result<fuse::FileAttr, c_int> {
+        if filetype_for_node(&child) != kind {
+            debug!("fuse: created {} is not a {:?}", name, kind);
+            return Err(libc::EEXIST);
+        }
+        self.add_lookup(parent_ino, name, child)
+    }
+
+    fn unlink(&self, parent_ino: u64, name: &str) -> Result<(), c_int> {
+        trace!("fuse unlink: {}, {}", parent_ino, name);
+
+        self.directory(parent_ino)?.unlink(name).map_err(|err| {
+            error!("fuse: could not unlink {}: {}", name, err);
+            err.errno()
+        })
+    }
+
+    fn getxattr(&self, ino: u64, name: &ffi::OsStr) -> Result<Vec<u8>, c_int> {
+        trace!("fuse getxattr: {}, {:?}", ino, name);
+
+        let node = self.node(ino)?;
+        let xattrs = node.xattrs().map_err(|err| {
+            error!("fuse: could not get xattrs for inode {}: {}", ino, err);
+            err.errno()
+        })?;
+        xattrs
+            .into_iter()
+            .find(|(n, _)| n.as_bytes() == name.as_bytes())
+            .map(|(_, value)| value.into_bytes())
+            .ok_or(libc::ENODATA)
+    }
+
+    /// Returns the names of the extended attributes of the node, each terminated by a NUL byte.
+    fn listxattr(&self, ino: u64) -> Result<Vec<u8>, c_int> {
+        trace!("fuse listxattr: {}", ino);
+
+        let node = self.node(ino)?;
+        let xattrs = node.xattrs().map_err(|err| {
+            error!("fuse: could not get xattrs for inode {}: {}", ino, err);
+            err.errno()
+        })?;
+        let mut names = Vec::new();
+        for (name, _) in xattrs {
+            names.extend(name.as_bytes());
+            names.push(0);
+        }
+        Ok(names)
+    }
+
+    fn inode_for_child(&self, parent_ino: u64, name: &str, child: &Node<N>) -> u64 {
+        self.inodes.inode(&inode_key(parent_ino, name, child))
+    }
+}
+
+/// Returns the key that identifies a node in the InodeTable.
+fn inode_key<N: NodeType>(parent_ino: u64, name: &str, child: &Node<N>) -> String {
+    child
+        .id()
+        .unwrap_or_else(|| format!("{}/{}", parent_ino, name))
+}
+
+/// Replies with the size of the data if the requested size is 0, which is how callers find out
+/// how large their buffer should be.
+fn reply_xattr(reply: fuse::ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(data);
+    }
 }
 
 fn filetype_for_node<N: NodeType>(node: &Node<N>) -> fuse::FileType {
@@ -573,8 +914,6 @@ fn attrs_for_file<N: NodeType>(
     uid: u32,
     gid: u32,
 ) -> Result<fuse::FileAttr, N::Error> {
-    const BLOCK_SIZE: u64 = 1024;
-
     let meta = node.metadata()?;
     let size = match node {
         Node::File(f) => f.size()?,
@@ -597,3 +936,131 @@ fn attrs_for_file<N: NodeType>(
         flags: 0,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::harness::Harness;
+    use super::Stats;
+    use crate::fixture::Fixture;
+
+    fn fixture() -> Fixture {
+        let json = r#"{
+            "type": "directory",
+            "entries": [
+                { "name": "a", "type": "directory", "entries": [
+                    { "name": "b", "type": "file", "content": "bbb" }
+                ] },
+                { "name": "c", "type": "file", "content": "ccc", "xattrs": {
+                    "user.x": "1", "user.y": "2"
+                } },
+                { "name": "d", "type": "file", "pattern": "d", "size": 2048 }
+            ]
+        }"#;
+        Fixture::from_reader(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn forget_evicts_nodes() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(1, fs.num_inodes());
+
+        let c = fs.lookup("c").unwrap().ino;
+        fs.lookup("c").unwrap();
+        assert_eq!(2, fs.num_inodes());
+        fs.forget(c, 1);
+        assert_eq!(2, fs.num_inodes());
+        fs.forget(c, 1);
+        assert_eq!(1, fs.num_inodes());
+    }
+
+    #[test]
+    fn forget_root() {
+        let fs = Harness::new(&fixture());
+        fs.forget(super::INO_ROOT, 1);
+        assert_eq!(1, fs.num_inodes());
+        assert_eq!(3, fs.list("").unwrap().len());
+    }
+
+    #[test]
+    fn forget_all() {
+        let fs = Harness::new(&fixture());
+        let a = fs.lookup("a").unwrap().ino;
+        let b = fs.lookup("a/b").unwrap().ino;
+        let d = fs.getattr("d").unwrap().ino;
+        assert_eq!(4, fs.num_inodes());
+        // lookup of a/b also looked up a.
+        fs.forget(a, 2);
+        fs.forget(b, 1);
+        fs.forget(d, 1);
+        assert_eq!(1, fs.num_inodes());
+        assert_eq!(Err(libc::ENOENT), fs.getattr_ino(b).map(|_| ()));
+
+        // The inode is numbered the same when it is looked up again.
+        assert_eq!(b, fs.lookup("a/b").unwrap().ino);
+        let fh = fs.open_ino(b).unwrap();
+        assert_eq!(b"bbb".to_vec(), fs.read_handle(b, fh, 0, 10).unwrap());
+        fs.release(fh);
+    }
+
+    #[test]
+    fn xattrs() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(vec!["user.x", "user.y"], fs.listxattr("c").unwrap());
+        assert_eq!(b"2".to_vec(), fs.getxattr("c", "user.y").unwrap());
+        assert_eq!(Err(libc::ENODATA), fs.getxattr("c", "user.z"));
+        assert!(fs.listxattr("a").unwrap().is_empty());
+    }
+
+    #[test]
+    fn statfs() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(
+            Stats {
+                blocks: 0,
+                files: 1
+            },
+            fs.statfs()
+        );
+        fs.lookup("a/b").unwrap();
+        let d = fs.getattr("d").unwrap().ino;
+        assert_eq!(
+            Stats {
+                blocks: 2,
+                files: 4
+            },
+            fs.statfs()
+        );
+        fs.forget(d, 1);
+        assert_eq!(
+            Stats {
+                blocks: 0,
+                files: 3
+            },
+            fs.statfs()
+        );
+    }
+
+    #[test]
+    fn name_too_long() {
+        let fs = Harness::new(&fixture());
+        let name = "x".repeat(256);
+        assert_eq!(Err(libc::ENAMETOOLONG), fs.lookup(&name).map(|_| ()));
+    }
+
+    #[test]
+    fn read_only() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(Err(libc::EROFS), fs.unlink("c"));
+        assert_eq!(Err(libc::EROFS), fs.symlink("c", "e").map(|_| ()));
+        assert_eq!(Err(libc::EROFS), fs.create("e").map(|_| ()));
+        assert_eq!(Err(libc::EROFS), fs.mkdir("e").map(|_| ()));
+        assert_eq!(Err(libc::EROFS), fs.link("c", "a/c").map(|_| ()));
+        assert_eq!(Err(libc::ENOTDIR), fs.unlink("c/x"));
+    }
+
+    #[test]
+    fn unknown_inode() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(Err(libc::ENOENT), fs.getattr_ino(1234).map(|_| ()));
+    }
+}
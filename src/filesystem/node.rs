@@ -2,10 +2,12 @@ use super::*;
 use chrono::{DateTime, Utc};
 use std::error;
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub trait Error: error::Error {
     fn not_found() -> Self;
+    /// The error for modifications of nodes that can not be modified.
+    fn read_only() -> Self;
     fn errno(&self) -> i32;
 }
 
@@ -19,10 +21,24 @@ pub struct Metadata {
 pub trait Meta {
     type Error: Error;
     fn metadata(&self) -> Result<Metadata, Self::Error>;
+
+    /// Returns a key that identifies the object represented by the node and that does not change
+    /// between mounts. Inode numbers are derived from it, nodes without an id are numbered by
+    /// their parent and name instead.
+    ///
+    /// Ids should not contain a '/', so they never equal the key of an unidentified node.
+    fn id(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the names and values of the extended attributes of the node.
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        Ok(Vec::new())
+    }
 }
 
 pub trait File: Meta {
-    type Reader: io::Read + io::Seek;
+    type Reader: io::Read + io::Seek + Send;
     fn open_ro(&self) -> Result<Self::Reader, Self::Error>;
     fn size(&self) -> Result<u64, Self::Error>;
 }
@@ -37,28 +53,74 @@ pub trait Directory<N: NodeType>: Meta {
             .map(|(_, entry)| entry)
             .ok_or_else(Self::Error::not_found)
     }
+
+    // The methods below modify the directory. They are not supported unless implemented.
+
+    /// Creates a new entry in the directory and returns it.
+    fn create(&self, _name: &str) -> Result<Node<N>, Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Adds an existing node to the directory and returns the resulting entry.
+    fn link(&self, _name: &str, _node: &Node<N>) -> Result<Node<N>, Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Handles the creation of a subdirectory and returns the directory to report for it. Like
+    /// with symlink, the directory may consume the new directory instead of listing it. If it
+    /// returns the node it lists under the name and that is not a directory, the creation fails
+    /// with EEXIST.
+    fn mkdir(&self, _name: &str) -> Result<Node<N>, Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Handles the creation of a symlink in the directory and returns the symlink to report for
+    /// it. The target is passed as is, so it may be relative to the directory or refer to
+    /// something outside of the filesystem.
+    ///
+    /// The directory does not need to list a symlink afterwards, it may for example consume the
+    /// link and list the target instead.
+    fn symlink(&self, _name: &str, _target: &Path) -> Result<Node<N>, Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Removes an entry from the directory.
+    fn unlink(&self, _name: &str) -> Result<(), Self::Error> {
+        Err(Self::Error::read_only())
+    }
 }
 
 pub trait Symlink: Meta {
     fn read_link(&self) -> Result<PathBuf, Self::Error>;
 }
 
-pub trait NodeType: Sized {
+// Nodes are shared between the threads that handle filesystem requests.
+pub trait NodeType: Sized + 'static {
     type Error: Error;
-    type File: File<Error = Self::Error>;
-    type Directory: Directory<Self, Error = Self::Error>;
-    type Symlink: Symlink<Error = Self::Error>;
+    type File: File<Error = Self::Error> + Clone + Send + Sync;
+    type Directory: Directory<Self, Error = Self::Error> + Clone + Send + Sync;
+    type Symlink: Symlink<Error = Self::Error> + Clone + Send + Sync;
 
     fn root(&self) -> Self::Directory;
 }
 
-#[derive(Clone)]
 pub enum Node<T: NodeType> {
     File(T::File),
     Directory(T::Directory),
     Symlink(T::Symlink),
 }
 
+// Implemented by hand, deriving would require T to be Clone as well.
+impl<T: NodeType> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Node::File(f) => Node::File(f.clone()),
+            Node::Directory(f) => Node::Directory(f.clone()),
+            Node::Symlink(f) => Node::Symlink(f.clone()),
+        }
+    }
+}
+
 impl<T: NodeType> Node<T> {
     pub fn file(&self) -> Option<&T::File> {
         match self {
@@ -91,4 +153,20 @@ impl<T: NodeType> Meta for Node<T> {
             Node::Symlink(f) => f.metadata(),
         }
     }
+
+    fn id(&self) -> Option<String> {
+        match self {
+            Node::File(f) => f.id(),
+            Node::Directory(f) => f.id(),
+            Node::Symlink(f) => f.id(),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        match self {
+            Node::File(f) => f.xattrs(),
+            Node::Directory(f) => f.xattrs(),
+            Node::Symlink(f) => f.xattrs(),
+        }
+    }
 }
@@ -1,38 +1,53 @@
 use super::*;
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Controls how long the results of directory operations are kept by a DirCache.
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+    /// How long directory listings and found entries remain valid.
+    pub ttl: Duration,
+    /// How long the absence of an entry remains valid. This is usually shorter than the ttl.
+    pub negative_ttl: Duration,
+    /// When set, a stale listing is returned while it is being refreshed in the background
+    /// instead of blocking until the refresh has completed.
+    pub refresh_in_background: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl: Duration::from_secs(60 * 60),
+            negative_ttl: Duration::from_secs(60),
+            refresh_in_background: false,
+        }
+    }
+}
 
-#[derive(Clone)]
 pub struct CacheRoot<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
     root: DirCache<N>,
 }
 
 impl<N> CacheRoot<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
-    pub fn new(inner: &N) -> Self {
+    pub fn new(inner: &N, config: CacheConfig) -> Self {
         CacheRoot {
-            root: DirCache::new(inner.root()),
+            root: DirCache::new(inner.root(), config),
         }
     }
 }
 
 impl<N> NodeType for CacheRoot<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
     type Error = N::Error;
     type File = N::File;
@@ -44,122 +59,343 @@ where
     }
 }
 
-#[derive(Clone)]
+type Listing<N> = Vec<(String, Node<CacheRoot<N>>)>;
+
+struct ListingState<N>
+where
+    N: NodeType,
+{
+    listing: Option<(Instant, Listing<N>)>,
+    /// Set while the listing is being fetched, so concurrent listings result in just one query.
+    fetching: bool,
+    /// Incremented whenever the directory is modified. A fetch that started before the
+    /// modification may not have seen it, so its result is not stored.
+    generation: u64,
+}
+
+/// DirCache wraps a directory and caches the results of the underlying directory's methods.
+///
+/// Clones of a DirCache share the same cache. The children of subdirectories are DirCaches as
+/// well, so a cached listing keeps the listings of its subdirectories alive until it expires,
+/// even after the kernel has forgotten their inodes.
 pub struct DirCache<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
     inner: N::Directory,
-    cached_files: RefCell<Option<Vec<(String, Node<CacheRoot<N>>)>>>,
-    hidden_cached_files: RefCell<HashMap<String, Node<CacheRoot<N>>>>,
-    non_files: RefCell<HashSet<String>>,
+    config: CacheConfig,
+    cached_files: Arc<(Mutex<ListingState<N>>, Condvar)>,
+    hidden_cached_files: Arc<RwLock<HashMap<String, (Instant, Node<CacheRoot<N>>)>>>,
+    non_files: Arc<RwLock<HashMap<String, Instant>>>,
 }
 
 impl<N> DirCache<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
-    pub fn new(inner: N::Directory) -> Self {
+    pub fn new(inner: N::Directory, config: CacheConfig) -> Self {
         DirCache {
             inner,
-            cached_files: RefCell::new(None),
-            hidden_cached_files: RefCell::new(HashMap::new()),
-            non_files: RefCell::new(HashSet::new()),
+            config,
+            cached_files: Arc::new((
+                Mutex::new(ListingState {
+                    listing: None,
+                    fetching: false,
+                    generation: 0,
+                }),
+                Condvar::new(),
+            )),
+            hidden_cached_files: Arc::new(RwLock::new(HashMap::new())),
+            non_files: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn listing_state(&self) -> MutexGuard<'_, ListingState<N>> {
+        self.cached_files.0.lock().unwrap()
+    }
+
+    fn fresh(&self, at: Instant) -> bool {
+        at.elapsed() < self.config.ttl
+    }
+
+    fn fetch_files(&self) -> Result<Listing<N>, N::Error> {
+        let files = self
+            .inner
+            .files()?
+            .into_iter()
+            .map(|(name, node)| (name, map_node(node, self.config)))
+            .collect();
+        Ok(files)
+    }
+
+    /// Fetches the listing without holding the lock and stores it, unless the directory was
+    /// modified in the meantime. The caller must have set the fetching flag.
+    fn fetch_and_store(&self, generation: u64) -> Result<Listing<N>, N::Error> {
+        let rs = self.fetch_files();
+        let mut state = self.listing_state();
+        state.fetching = false;
+        if let Ok(files) = &rs {
+            if state.generation == generation {
+                state.listing = Some((Instant::now(), files.clone()));
+            }
+        }
+        self.cached_files.1.notify_all();
+        rs
+    }
+
+    /// Drops everything that is known about the directory after it has been modified.
+    fn invalidate(&self) {
+        let mut state = self.listing_state();
+        state.listing = None;
+        state.generation += 1;
+        drop(state);
+        self.hidden_cached_files.write().unwrap().clear();
+        self.non_files.write().unwrap().clear();
+    }
+
+    /// Starts a thread to refresh the listing, unless a fetch is already in progress.
+    fn refresh_in_background(&self, state: &mut ListingState<N>) {
+        if state.fetching {
+            return;
+        }
+        state.fetching = true;
+        let generation = state.generation;
+        let dir = self.clone();
+        thread::spawn(move || {
+            if let Err(err) = dir.fetch_and_store(generation) {
+                error!("could not refresh directory listing: {}", err);
+            }
+        });
+    }
+}
+
+impl<N> Clone for DirCache<N>
+where
+    N: NodeType,
+{
+    fn clone(&self) -> Self {
+        DirCache {
+            inner: self.inner.clone(),
+            config: self.config,
+            cached_files: self.cached_files.clone(),
+            hidden_cached_files: self.hidden_cached_files.clone(),
+            non_files: self.non_files.clone(),
         }
     }
 }
 
 impl<N> Meta for DirCache<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
     type Error = N::Error;
     fn metadata(&self) -> Result<Metadata, Self::Error> {
         self.inner.metadata()
     }
+
+    fn id(&self) -> Option<String> {
+        self.inner.id()
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        self.inner.xattrs()
+    }
 }
 
 impl<N> Directory<CacheRoot<N>> for DirCache<N>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
     fn files(&self) -> Result<Vec<(String, Node<CacheRoot<N>>)>, Self::Error> {
-        let mut cached = self.cached_files.borrow_mut();
-        if cached.is_some() {
-            return Ok(cached.as_ref().unwrap().to_vec());
+        let mut state = self.listing_state();
+        loop {
+            match state.listing.as_ref() {
+                Some((at, files)) if self.fresh(*at) => return Ok(files.to_vec()),
+                Some((_, files)) if self.config.refresh_in_background => {
+                    let files = files.to_vec();
+                    self.refresh_in_background(&mut state);
+                    return Ok(files);
+                }
+                _ => (),
+            }
+            if !state.fetching {
+                break;
+            }
+            // Wait for the listing that is already being fetched instead of querying again.
+            state = self.cached_files.1.wait(state).unwrap();
         }
-        let files: Vec<_> = self
-            .inner
-            .files()?
-            .into_iter()
-            .map(|(name, node)| (name, map_node(node)))
-            .collect();
-        *cached = Some(files.clone());
-        Ok(files)
+        state.fetching = true;
+        let generation = state.generation;
+        drop(state);
+        self.fetch_and_store(generation)
     }
 
     fn file_by_name(&self, name: &str) -> Result<Node<CacheRoot<N>>, Self::Error> {
-        if self.non_files.borrow().contains(name) {
-            return Err(Self::Error::not_found());
+        if let Some(at) = self.non_files.read().unwrap().get(name) {
+            if at.elapsed() < self.config.negative_ttl {
+                return Err(Self::Error::not_found());
+            }
         }
 
-        if let Some(node) = self.hidden_cached_files.borrow().get(name) {
-            return Ok(node.clone());
+        if let Some((at, node)) = self.hidden_cached_files.read().unwrap().get(name) {
+            if self.fresh(*at) {
+                return Ok(node.clone());
+            }
         }
 
-        let cached = self.cached_files.borrow_mut();
-        if cached.is_some() {
-            let maybe_node = cached
-                .as_ref()
-                .unwrap()
+        if let Some((at, files)) = self.listing_state().listing.as_ref() {
+            let maybe_node = files
                 .iter()
                 .find(|(n, _)| n == name)
-                .map(|(_, entry)| entry);
+                .map(|(_, entry)| entry)
+                .filter(|_| self.fresh(*at));
             if let Some(node) = maybe_node {
                 return Ok(node.clone());
             }
         }
 
         match self.inner.file_by_name(name) {
+            // Expired entries are dropped whenever a new one is added, so names that are looked
+            // up just once do not pile up.
             Ok(node) => {
-                let node = map_node(node);
-                self.hidden_cached_files
-                    .borrow_mut()
-                    .insert(name.to_string(), node.clone());
+                let node = map_node(node, self.config);
+                self.non_files.write().unwrap().remove(name);
+                let mut hidden = self.hidden_cached_files.write().unwrap();
+                hidden.retain(|_, (at, _)| self.fresh(*at));
+                hidden.insert(name.to_string(), (Instant::now(), node.clone()));
                 Ok(node)
             }
             Err(err) => {
                 if err.errno() == libc::ENOENT {
-                    self.non_files.borrow_mut().insert(name.to_string());
+                    let mut non_files = self.non_files.write().unwrap();
+                    non_files.retain(|_, at| at.elapsed() < self.config.negative_ttl);
+                    non_files.insert(name.to_string(), Instant::now());
                 }
                 Err(err)
             }
         }
     }
+
+    fn create(&self, name: &str) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        let node = self.inner.create(name)?;
+        self.invalidate();
+        Ok(map_node(node, self.config))
+    }
+
+    fn link(
+        &self,
+        name: &str,
+        node: &Node<CacheRoot<N>>,
+    ) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        let node = self.inner.link(name, &unmap_node(node))?;
+        self.invalidate();
+        Ok(map_node(node, self.config))
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        let node = self.inner.mkdir(name)?;
+        self.invalidate();
+        Ok(map_node(node, self.config))
+    }
+
+    fn symlink(&self, name: &str, target: &Path) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        let node = self.inner.symlink(name, target)?;
+        self.invalidate();
+        Ok(map_node(node, self.config))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        self.inner.unlink(name)?;
+        self.invalidate();
+        Ok(())
+    }
 }
 
-fn map_node<N>(node: Node<N>) -> Node<CacheRoot<N>>
+fn map_node<N>(node: Node<N>, config: CacheConfig) -> Node<CacheRoot<N>>
 where
-    N: NodeType + Clone,
-    N::File: Clone,
-    N::Directory: Clone,
-    N::Symlink: Clone,
+    N: NodeType,
 {
     match node {
         Node::File(f) => Node::File(f),
-        Node::Directory(f) => Node::Directory(DirCache::new(f)),
+        Node::Directory(f) => Node::Directory(DirCache::new(f, config)),
         Node::Symlink(f) => Node::Symlink(f),
     }
 }
+
+fn unmap_node<N>(node: &Node<CacheRoot<N>>) -> Node<N>
+where
+    N: NodeType,
+{
+    match node {
+        Node::File(f) => Node::File(f.clone()),
+        Node::Directory(f) => Node::Directory(f.inner.clone()),
+        Node::Symlink(f) => Node::Symlink(f.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    const FIXTURE: &str = r#"{
+        "type": "directory",
+        "entries": [
+            { "name": "slow", "type": "directory", "delay_ms": 200, "entries": [
+                { "name": "a", "type": "file" }
+            ] }
+        ]
+    }"#;
+
+    fn slow_dir(config: CacheConfig) -> DirCache<Fixture> {
+        let fixture = Fixture::from_reader(FIXTURE.as_bytes()).unwrap();
+        match CacheRoot::new(&fixture, config).root().file_by_name("slow") {
+            Ok(Node::Directory(dir)) => dir,
+            _ => panic!("not a directory"),
+        }
+    }
+
+    #[test]
+    fn fetch_without_lock() {
+        let dir = slow_dir(CacheConfig::default());
+        let listing = {
+            let dir = dir.clone();
+            thread::spawn(move || dir.files().map(|files| files.len()))
+        };
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        dir.invalidate();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(1, listing.join().unwrap().unwrap());
+
+        // Listings that wait for the one in progress get its result.
+        let listings: Vec<_> = (0..4)
+            .map(|_| {
+                let dir = dir.clone();
+                thread::spawn(move || dir.files().map(|files| files.len()))
+            })
+            .collect();
+        for listing in listings {
+            assert_eq!(1, listing.join().unwrap().unwrap());
+        }
+        assert!(dir.listing_state().listing.is_some());
+    }
+
+    #[test]
+    fn refresh_after_invalidate() {
+        let dir = slow_dir(CacheConfig {
+            ttl: Duration::from_secs(0),
+            refresh_in_background: true,
+            ..CacheConfig::default()
+        });
+        dir.files().unwrap();
+        // The stale listing is returned while a refresh is started.
+        assert_eq!(1, dir.files().unwrap().len());
+        dir.invalidate();
+        thread::sleep(Duration::from_millis(400));
+        // The refresh started before the directory was modified, so its result is dropped.
+        let state = dir.listing_state();
+        assert!(!state.fetching);
+        assert!(state.listing.is_none());
+    }
+}
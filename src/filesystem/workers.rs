@@ -0,0 +1,77 @@
+use log::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads that execute jobs in the order in which they were submitted.
+///
+/// Unlike a work stealing pool, a worker never picks up another job while it is blocked inside a
+/// job, so jobs are free to hold locks while performing blocking operations.
+pub struct WorkerPool {
+    sender: mpsc::Sender<Job>,
+}
+
+impl WorkerPool {
+    pub fn new(num_threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..num_threads.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("fuse-worker-{}", i))
+                .spawn(move || loop {
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(v) => v,
+                        // The sending half is dropped when the pool is dropped.
+                        Err(_) => break,
+                    };
+                    // Keep the worker alive if a job panics. The reply of the job is dropped
+                    // during unwinding, which reports an error to the kernel.
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("fuse: worker job panicked");
+                    }
+                })
+                .expect("could not spawn worker thread");
+        }
+        WorkerPool { sender }
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.sender
+            .send(Box::new(job))
+            .expect("worker threads should outlive the pool");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn concurrent_jobs() {
+        const N: usize = 4;
+        let pool = WorkerPool::new(N);
+        // All jobs can only pass the barrier if they are running at the same time.
+        let barrier = Arc::new(Barrier::new(N + 1));
+        for _ in 0..N {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+    }
+
+    #[test]
+    fn survive_panic() {
+        let pool = WorkerPool::new(1);
+        pool.execute(|| panic!("oh no"));
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap());
+        rx.recv().unwrap();
+    }
+}
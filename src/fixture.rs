@@ -0,0 +1,428 @@
+//! A filesystem tree that is described by a JSON document instead of being backed by SoundCloud.
+//!
+//! This is used to test and benchmark the filesystem layer deterministically and to reproduce
+//! problems without needing access to SoundCloud. A fixture looks like this:
+//!
+//! ```json
+//! {
+//!   "type": "directory",
+//!   "entries": [
+//!     { "name": "hello.txt", "type": "file", "content": "Hello, World!\n", "mtime": 1546398245 },
+//!     { "name": "big.bin", "type": "file", "pattern": "0123456789", "size": 1048576 },
+//!     { "name": "link", "type": "symlink", "target": "hello.txt", "xattrs": { "user.a": "b" } },
+//!     { "name": "broken", "type": "directory", "error": "EACCES" },
+//!     { "name": "slow.mp3", "type": "file", "content": "...", "delay_ms": 500, "read_error_at": 2 }
+//!   ]
+//! }
+//! ```
+//!
+//! The `error` of a node is returned when listing a directory, opening a file or reading a symlink.
+//! The `delay_ms` is added to those same operations.
+
+use crate::filesystem;
+use crate::ioutil::{Pattern, ReadSeek};
+use chrono::{TimeZone, Utc};
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    Errno(i32),
+    Invalid(String),
+
+    IOError(io::Error),
+    JSONError(serde_json::Error),
+}
+
+impl filesystem::Error for Error {
+    fn not_found() -> Self {
+        Error::Errno(libc::ENOENT)
+    }
+
+    fn read_only() -> Self {
+        Error::Errno(libc::EROFS)
+    }
+
+    fn errno(&self) -> i32 {
+        match self {
+            Error::Errno(errno) => *errno,
+            Error::IOError(err) => err.raw_os_error().unwrap_or(libc::EIO),
+            _ => libc::EIO,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IOError(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::JSONError(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Directory,
+    File,
+    Symlink,
+}
+
+#[derive(Debug, Deserialize)]
+struct Spec {
+    #[serde(rename = "type")]
+    kind: Kind,
+    /// The name of the node in its parent directory, unused for the root.
+    #[serde(default)]
+    name: String,
+    /// Seconds since the UNIX epoch.
+    #[serde(default)]
+    mtime: i64,
+    perm: Option<u16>,
+    /// The name or number of the errno to fail with.
+    error: Option<String>,
+    #[serde(default)]
+    delay_ms: u64,
+    #[serde(default)]
+    xattrs: BTreeMap<String, String>,
+
+    // Directories.
+    #[serde(default)]
+    entries: Vec<Spec>,
+
+    // Files, the content is either literal or a pattern that is repeated up to the size.
+    #[serde(default)]
+    content: String,
+    pattern: Option<String>,
+    size: Option<u64>,
+    /// Reads at or beyond this offset fail with EIO.
+    read_error_at: Option<u64>,
+
+    // Symlinks.
+    target: Option<PathBuf>,
+}
+
+impl Spec {
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |msg: &str| Err(Error::Invalid(format!("{:?}: {}", self.name, msg)));
+        if let Some(err) = &self.error {
+            if parse_errno(err).is_none() {
+                return invalid("unknown errno");
+            }
+        }
+        match self.kind {
+            Kind::Symlink if self.target.is_none() => return invalid("symlink without target"),
+            Kind::File if self.pattern.is_some() && self.size.is_none() => {
+                return invalid("pattern without size");
+            }
+            _ => (),
+        }
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.name.is_empty() || entry.name.contains('/') {
+                return invalid("entries must have a valid name");
+            }
+            if self.entries[..i].iter().any(|e| e.name == entry.name) {
+                return invalid("duplicate entry");
+            }
+            entry.validate()?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_errno(s: &str) -> Option<i32> {
+    let errno = match s {
+        "EACCES" => libc::EACCES,
+        "EIO" => libc::EIO,
+        "ENOENT" => libc::ENOENT,
+        "ENOTDIR" => libc::ENOTDIR,
+        "EPERM" => libc::EPERM,
+        "ETIMEDOUT" => libc::ETIMEDOUT,
+        s => return s.parse().ok(),
+    };
+    Some(errno)
+}
+
+#[derive(Clone)]
+pub struct Fixture {
+    root: Arc<Spec>,
+}
+
+impl Fixture {
+    pub fn from_reader(r: impl io::Read) -> Result<Self, Error> {
+        let root: Spec = serde_json::from_reader(r)?;
+        if root.kind != Kind::Directory {
+            return Err(Error::Invalid("the root must be a directory".to_string()));
+        }
+        root.validate()?;
+        Ok(Fixture {
+            root: Arc::new(root),
+        })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Fixture::from_reader(io::BufReader::new(fs::File::open(path)?))
+    }
+}
+
+impl filesystem::NodeType for Fixture {
+    type Error = Error;
+    type File = FixtureNode;
+    type Directory = FixtureNode;
+    type Symlink = FixtureNode;
+
+    fn root(&self) -> Self::Directory {
+        FixtureNode {
+            root: self.root.clone(),
+            path: Vec::new(),
+        }
+    }
+}
+
+/// FixtureNode refers to a node in the fixture by the indices of the entries leading up to it.
+#[derive(Clone)]
+pub struct FixtureNode {
+    root: Arc<Spec>,
+    path: Vec<usize>,
+}
+
+impl FixtureNode {
+    fn spec(&self) -> &Spec {
+        self.path
+            .iter()
+            .fold(&self.root, |spec, &i| &spec.entries[i])
+    }
+
+    /// Simulates the latency and failures of the node.
+    fn access(&self) -> Result<&Spec, Error> {
+        let spec = self.spec();
+        if spec.delay_ms > 0 {
+            thread::sleep(Duration::from_millis(spec.delay_ms));
+        }
+        match spec.error.as_ref().and_then(|e| parse_errno(e)) {
+            Some(errno) => Err(Error::Errno(errno)),
+            None => Ok(spec),
+        }
+    }
+}
+
+impl filesystem::Meta for FixtureNode {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        let spec = self.spec();
+        let time = Utc.timestamp(spec.mtime, 0);
+        let default_perm = match spec.kind {
+            Kind::Directory => 0o555,
+            _ => 0o444,
+        };
+        Ok(filesystem::Metadata {
+            mtime: time,
+            ctime: time,
+            perm: spec.perm.unwrap_or(default_perm),
+        })
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        let spec = self.spec();
+        Ok(spec.xattrs.clone().into_iter().collect())
+    }
+}
+
+impl filesystem::Directory<Fixture> for FixtureNode {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Fixture>)>, Self::Error> {
+        let files = self
+            .access()?
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let mut path = self.path.clone();
+                path.push(i);
+                let node = FixtureNode {
+                    root: self.root.clone(),
+                    path,
+                };
+                let node = match entry.kind {
+                    Kind::Directory => filesystem::Node::Directory(node),
+                    Kind::File => filesystem::Node::File(node),
+                    Kind::Symlink => filesystem::Node::Symlink(node),
+                };
+                (entry.name.clone(), node)
+            })
+            .collect();
+        Ok(files)
+    }
+}
+
+impl filesystem::File for FixtureNode {
+    type Reader = FailAt<Box<dyn ReadSeek + Send>>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        let spec = self.access()?;
+        let inner = match &spec.pattern {
+            Some(pattern) => Box::<dyn ReadSeek + Send>::from(Box::new(Pattern::new(
+                pattern.clone().into_bytes(),
+                spec.size.unwrap_or(0),
+            ))),
+            None => Box::<dyn ReadSeek + Send>::from(Box::new(io::Cursor::new(
+                spec.content.clone().into_bytes(),
+            ))),
+        };
+        Ok(FailAt {
+            inner,
+            offset: 0,
+            fail_at: spec.read_error_at,
+        })
+    }
+
+    fn size(&self) -> Result<u64, Self::Error> {
+        let spec = self.spec();
+        Ok(match spec.pattern {
+            Some(_) => spec.size.unwrap_or(0),
+            None => spec.content.len() as u64,
+        })
+    }
+}
+
+impl filesystem::Symlink for FixtureNode {
+    fn read_link(&self) -> Result<PathBuf, Self::Error> {
+        let spec = self.access()?;
+        Ok(spec.target.clone().unwrap_or_default())
+    }
+}
+
+/// FailAt fails all reads that touch the specified offset or anything beyond it.
+pub struct FailAt<T> {
+    inner: T,
+    offset: u64,
+    fail_at: Option<u64>,
+}
+
+impl<T: io::Read> io::Read for FailAt<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(fail_at) = self.fail_at {
+            if self.offset + buf.len() as u64 > fail_at {
+                return Err(io::Error::from_raw_os_error(libc::EIO));
+            }
+        }
+        let n = self.inner.read(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: io::Seek> io::Seek for FailAt<T> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.offset = self.inner.seek(pos)?;
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::harness::Harness;
+    use crate::filesystem::{CacheConfig, CacheRoot};
+    use std::time::Instant;
+
+    const FIXTURE: &str = r#"{
+        "type": "directory",
+        "entries": [
+            { "name": "hello.txt", "type": "file", "content": "Hello, World!\n", "mtime": 1546398245 },
+            { "name": "big.bin", "type": "file", "pattern": "0123456789", "size": 1000000 },
+            { "name": "link", "type": "symlink", "target": "hello.txt" },
+            { "name": "broken", "type": "directory", "error": "EACCES" },
+            { "name": "flaky.bin", "type": "file", "content": "abcdef", "read_error_at": 3 },
+            { "name": "slow", "type": "directory", "delay_ms": 100, "entries": [
+                { "name": "a", "type": "file" }
+            ] }
+        ]
+    }"#;
+
+    fn fixture() -> Fixture {
+        Fixture::from_reader(FIXTURE.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn list() {
+        let fs = Harness::new(&fixture());
+        let names: Vec<_> = fs.list("").unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(
+            vec![
+                "hello.txt",
+                "big.bin",
+                "link",
+                "broken",
+                "flaky.bin",
+                "slow"
+            ],
+            names
+        );
+        assert_eq!(Err(libc::EACCES), fs.list("broken"));
+    }
+
+    #[test]
+    fn read() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(
+            b"Hello, World!\n".to_vec(),
+            fs.read_all("hello.txt").unwrap()
+        );
+        assert_eq!(1546398245, fs.getattr("hello.txt").unwrap().mtime.sec);
+
+        assert_eq!(1_000_000, fs.getattr("big.bin").unwrap().size);
+        assert_eq!(b"34567".to_vec(), fs.read("big.bin", 3, 5).unwrap());
+        assert_eq!(b"56789".to_vec(), fs.read("big.bin", 999_995, 10).unwrap());
+
+        assert_eq!(b"ab".to_vec(), fs.read("flaky.bin", 0, 2).unwrap());
+        assert_eq!(Err(libc::EIO), fs.read("flaky.bin", 2, 2));
+    }
+
+    #[test]
+    fn symlink() {
+        let fs = Harness::new(&fixture());
+        assert_eq!(PathBuf::from("hello.txt"), fs.readlink("link").unwrap());
+        assert_eq!(Err(libc::EINVAL), fs.readlink("hello.txt"));
+    }
+
+    #[test]
+    fn cached() {
+        let fs = Harness::new(&CacheRoot::new(&fixture(), CacheConfig::default()));
+        fs.list("slow").unwrap();
+        let start = Instant::now();
+        fs.list("slow").unwrap();
+        fs.lookup("slow/a").unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |s: &str| Fixture::from_reader(s.as_bytes()).map(|_| ());
+        assert!(parse(r#"{ "type": "file" }"#).is_err());
+        assert!(parse(
+            r#"{ "type": "directory", "entries": [{ "type": "symlink", "name": "a" }] }"#
+        )
+        .is_err());
+        assert!(parse(r#"{ "type": "directory", "error": "EWHAT" }"#).is_err());
+        assert!(parse(r#"{ "type": "directory", "entries": [{ "type": "file" }] }"#).is_err());
+    }
+}
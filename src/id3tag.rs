@@ -4,8 +4,13 @@ use id3;
 use log::*;
 use std::io;
 
+/// Builds the ID3 tag for a track.
+///
+/// If the track is served as part of a playlist, the playlist and the track's index in it should
+/// be passed so the album frames can be populated.
 pub fn tag_for_track(
     track: &soundcloud::Track,
+    playlist: Option<(&soundcloud::Playlist, usize)>,
     enable_artwork: bool,
     parse_strings: bool,
 ) -> Result<impl io::Read + io::Seek, soundcloud::Error> {
@@ -65,6 +70,13 @@ pub fn tag_for_track(
         tag.set_text("TSRC", isrc.as_str());
     }
 
+    if let Some((playlist, index)) = playlist {
+        tag.set_album(playlist.title.as_str());
+        tag.set_album_artist(playlist.user.username.as_str());
+        tag.set_track(index as u32 + 1);
+        tag.set_total_tracks(playlist.tracks.len() as u32);
+    }
+
     if enable_artwork {
         match track.artwork() {
             Err(soundcloud::Error::ArtworkNotAvailable) => (),
@@ -0,0 +1,568 @@
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The number of bytes that may be added to an entry before its ranges are written to disk
+/// again, so not all of a long download is lost if the process does not exit cleanly.
+const SAVE_INTERVAL: u64 = 1 << 20;
+
+/// BlockCache keeps sparse copies of files on disk, identified by a numeric ID.
+///
+/// For every file, the cache records which byte ranges have been stored. When the total number
+/// of stored bytes exceeds the configured limit, the least recently used files are evicted.
+///
+/// Clones of a BlockCache share the same state.
+#[derive(Clone)]
+pub struct BlockCache {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    dir: PathBuf,
+    max_bytes: u64,
+    total_bytes: u64,
+    clock: u64,
+    entries: HashMap<u64, Entry>,
+}
+
+#[derive(Default)]
+struct Entry {
+    /// Sorted, non-overlapping and non-adjacent ranges of bytes that are present.
+    ranges: Vec<Range<u64>>,
+    last_used: u64,
+    num_open: usize,
+    /// The number of bytes added since the ranges were last saved.
+    unsaved: u64,
+}
+
+impl Entry {
+    fn size(&self) -> u64 {
+        self.ranges.iter().map(|r| r.end - r.start).sum()
+    }
+
+    /// Returns the range that contains the offset, or the start of the next stored range.
+    fn lookup(&self, offset: u64) -> Result<Range<u64>, Option<u64>> {
+        match self.ranges.iter().find(|r| offset < r.end) {
+            Some(r) if r.start <= offset => Ok(r.clone()),
+            Some(r) => Err(Some(r.start)),
+            None => Err(None),
+        }
+    }
+
+    fn insert(&mut self, range: Range<u64>) {
+        let mut new = range;
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        for r in self.ranges.drain(..) {
+            if r.end < new.start || new.end < r.start {
+                ranges.push(r);
+            } else {
+                new = new.start.min(r.start)..new.end.max(r.end);
+            }
+        }
+        let i = ranges.iter().position(|r| new.start < r.start);
+        ranges.insert(i.unwrap_or(ranges.len()), new);
+        self.ranges = ranges;
+    }
+}
+
+impl BlockCache {
+    /// Opens a cache in the specified directory. Files that were stored by a previous instance
+    /// are picked up again, files of which it is not known what they contain are removed.
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<BlockCache> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        // Restore the entries, ordered by the time they were last used.
+        let mut found = Vec::new();
+        let mut orphans = Vec::new();
+        for dirent in fs::read_dir(&dir)? {
+            let path = dirent?.path();
+            let id: u64 = match path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let extension = path.extension().and_then(|e| e.to_str());
+            if extension == Some("data") {
+                // Data is only usable if it is known which parts of it were written.
+                if !path.with_extension("ranges").exists() {
+                    orphans.push(path);
+                }
+                continue;
+            }
+            if extension != Some("ranges") {
+                continue;
+            }
+            let ranges = match read_ranges(&path) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("ignoring cache entry {}: {}", path.display(), err);
+                    orphans.push(path.with_extension("data"));
+                    orphans.push(path);
+                    continue;
+                }
+            };
+            if !ranges.is_empty() && !path.with_extension("data").exists() {
+                orphans.push(path);
+                continue;
+            }
+            let mtime = fs::metadata(&path)?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((mtime, id, ranges));
+        }
+        found.sort_by_key(|(mtime, _, _)| *mtime);
+        for path in orphans {
+            debug!("removing orphaned cache file {}", path.display());
+            remove_file(&path);
+        }
+
+        let mut state = State {
+            dir,
+            max_bytes,
+            total_bytes: 0,
+            clock: 0,
+            entries: HashMap::new(),
+        };
+        for (_, id, ranges) in found {
+            state.clock += 1;
+            let mut entry = Entry {
+                last_used: state.clock,
+                ..Entry::default()
+            };
+            for r in ranges {
+                entry.insert(r);
+            }
+            state.total_bytes += entry.size();
+            state.entries.insert(id, entry);
+        }
+        state.evict();
+        Ok(BlockCache {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Wraps the specified file so reads are served from the cache where possible. The inner
+    /// file is only read from for the ranges that are not yet cached.
+    ///
+    /// Nothing is written to disk until data is actually read.
+    pub fn open<T>(&self, id: u64, inner: T) -> io::Result<CachedFile<T>>
+    where
+        T: io::Read + io::Seek,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.entry(id).or_default();
+        entry.last_used = clock;
+        entry.num_open += 1;
+        Ok(CachedFile {
+            cache: self.clone(),
+            id,
+            inner,
+            inner_offset: None,
+            data: None,
+            offset: 0,
+        })
+    }
+}
+
+impl State {
+    fn data_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.data", id))
+    }
+
+    fn ranges_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.ranges", id))
+    }
+
+    fn save_ranges(&mut self, id: u64) {
+        let path = self.ranges_path(id);
+        let entry = match self.entries.get_mut(&id) {
+            Some(v) if !v.ranges.is_empty() => v,
+            _ => return,
+        };
+        entry.unsaved = 0;
+        let rs = fs::File::create(&path).and_then(|mut f| {
+            for r in &entry.ranges {
+                writeln!(f, "{} {}", r.start, r.end)?;
+            }
+            Ok(())
+        });
+        if let Err(err) = rs {
+            warn!("could not write cache entry {}: {}", path.display(), err);
+        }
+    }
+
+    /// Removes the least recently used entries that are not open until the total size is within
+    /// the limit.
+    fn evict(&mut self) {
+        while self.total_bytes > self.max_bytes {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|(_, e)| e.num_open == 0)
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(id, _)| *id);
+            let id = match victim {
+                Some(v) => v,
+                None => break,
+            };
+            let entry = self.entries.remove(&id).unwrap();
+            self.total_bytes -= entry.size();
+            debug!(
+                "evicting {} bytes of {} from the block cache",
+                entry.size(),
+                id
+            );
+            remove_file(&self.ranges_path(id));
+            remove_file(&self.data_path(id));
+        }
+    }
+}
+
+fn remove_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            warn!("could not remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn read_ranges(path: &Path) -> io::Result<Vec<Range<u64>>> {
+    let file = io::BufReader::new(fs::File::open(path)?);
+    file.lines()
+        .map(|line| {
+            let line = line?;
+            let mut nums = line.split(' ').map(|s| s.parse::<u64>());
+            match (nums.next(), nums.next()) {
+                (Some(Ok(start)), Some(Ok(end))) if start <= end => Ok(start..end),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad range: {:?}", line),
+                )),
+            }
+        })
+        .collect()
+}
+
+/// A file that is backed by a BlockCache, see BlockCache::open.
+pub struct CachedFile<T>
+where
+    T: io::Read + io::Seek,
+{
+    cache: BlockCache,
+    id: u64,
+
+    inner: T,
+    // The offset of the inner file, if known. Used to avoid needless seeks.
+    inner_offset: Option<u64>,
+
+    // The file holding the cached data, opened on first use.
+    data: Option<fs::File>,
+    offset: u64,
+}
+
+impl<T> CachedFile<T>
+where
+    T: io::Read + io::Seek,
+{
+    fn data(&mut self) -> io::Result<&fs::File> {
+        if self.data.is_none() {
+            let path = self.cache.state.lock().unwrap().data_path(self.id);
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            self.data = Some(file);
+        }
+        Ok(self.data.as_ref().unwrap())
+    }
+}
+
+impl<T> io::Read for CachedFile<T>
+where
+    T: io::Read + io::Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let lookup = {
+            let state = self.cache.state.lock().unwrap();
+            match state.entries.get(&self.id) {
+                Some(entry) => entry.lookup(self.offset),
+                None => Err(None),
+            }
+        };
+
+        let nread = match lookup {
+            Ok(range) => {
+                let len = buf.len().min((range.end - self.offset) as usize);
+                let offset = self.offset;
+                self.data()?.read_at(&mut buf[..len], offset)?
+            }
+            Err(next_start) => {
+                // Do not read into the next range that is already cached.
+                let len = match next_start {
+                    Some(start) => buf.len().min((start - self.offset) as usize),
+                    None => buf.len(),
+                };
+                if self.inner_offset != Some(self.offset) {
+                    self.inner.seek(io::SeekFrom::Start(self.offset))?;
+                }
+                let n = self.inner.read(&mut buf[..len])?;
+                self.inner_offset = Some(self.offset + n as u64);
+                if n > 0 {
+                    let offset = self.offset;
+                    self.data()?.write_all_at(&buf[..n], offset)?;
+                    let mut state = self.cache.state.lock().unwrap();
+                    let mut save = false;
+                    if let Some(entry) = state.entries.get_mut(&self.id) {
+                        let before = entry.size();
+                        entry.insert(offset..offset + n as u64);
+                        let growth = entry.size() - before;
+                        entry.unsaved += growth;
+                        save = entry.unsaved >= SAVE_INTERVAL;
+                        state.total_bytes += growth;
+                    }
+                    if save {
+                        state.save_ranges(self.id);
+                    }
+                    state.evict();
+                }
+                n
+            }
+        };
+        self.offset += nread as u64;
+        Ok(nread)
+    }
+}
+
+impl<T> io::Seek for CachedFile<T>
+where
+    T: io::Read + io::Seek,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let new_offset = match pos {
+            io::SeekFrom::Start(offset) => offset as i64,
+            io::SeekFrom::Current(offset) => self.offset as i64 + offset,
+            io::SeekFrom::End(_) => {
+                // Only the inner file knows its size.
+                let offset = self.inner.seek(pos)?;
+                self.inner_offset = Some(offset);
+                offset as i64
+            }
+        };
+        if new_offset < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "ioutil::CachedFile: seek position {:?} resolves to {}",
+                    pos, new_offset
+                ),
+            ));
+        }
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+impl<T> Drop for CachedFile<T>
+where
+    T: io::Read + io::Seek,
+{
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock().unwrap();
+        state.save_ranges(self.id);
+        if let Some(entry) = state.entries.get_mut(&self.id) {
+            entry.num_open -= 1;
+            // Entries of files that were opened without reading anything are not kept.
+            if entry.num_open == 0 && entry.ranges.is_empty() {
+                state.entries.remove(&self.id);
+            }
+        }
+        state.evict();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{OpRecorder, Operation};
+    use super::*;
+    use std::env;
+    use std::io::{Read, Seek};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "soundcloud-fs-test-blockcache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn inner_reads<T>(f: &CachedFile<OpRecorder<T>>) -> usize
+    where
+        T: io::Read + io::Seek,
+    {
+        f.inner
+            .ops()
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Read { nread, .. } => Some(*nread),
+                _ => None,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn entry_insert() {
+        let mut e = Entry::default();
+        e.insert(10..20);
+        e.insert(0..5);
+        e.insert(30..40);
+        assert_eq!(e.ranges, vec![0..5, 10..20, 30..40]);
+        e.insert(5..10);
+        assert_eq!(e.ranges, vec![0..20, 30..40]);
+        e.insert(15..35);
+        assert_eq!(e.ranges, vec![0..40]);
+        assert_eq!(e.size(), 40);
+    }
+
+    #[test]
+    fn read_twice() {
+        let dir = temp_dir("read_twice");
+        let data: Vec<u8> = (0..64).collect();
+        let cache = BlockCache::new(&dir, 1024).unwrap();
+
+        let mut f = cache
+            .open(1, OpRecorder::new(io::Cursor::new(data.clone())))
+            .unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert_eq!(data, buf);
+        assert_eq!(64, inner_reads(&f));
+        drop(f);
+
+        // A new instance should pick up the entries stored on disk.
+        let cache = BlockCache::new(&dir, 1024).unwrap();
+        let mut f = cache
+            .open(1, OpRecorder::new(io::Cursor::new(data.clone())))
+            .unwrap();
+        f.seek(io::SeekFrom::Start(16)).unwrap();
+        let mut buf = [0; 16];
+        f.read_exact(&mut buf).unwrap();
+        assert_eq!(data[16..32], buf);
+        assert_eq!(0, inner_reads(&f));
+        drop(f);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_gaps() {
+        let dir = temp_dir("read_gaps");
+        let data: Vec<u8> = (0..64).collect();
+        let cache = BlockCache::new(&dir, 1024).unwrap();
+
+        let mut f = cache
+            .open(1, OpRecorder::new(io::Cursor::new(data.clone())))
+            .unwrap();
+        f.seek(io::SeekFrom::Start(16)).unwrap();
+        let mut buf = [0; 16];
+        f.read_exact(&mut buf).unwrap();
+        assert_eq!(16, inner_reads(&f));
+
+        f.seek(io::SeekFrom::Start(0)).unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert_eq!(data, buf);
+        // Only the missing 48 bytes should have been fetched.
+        assert_eq!(64, inner_reads(&f));
+        drop(f);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evict_lru() {
+        let dir = temp_dir("evict_lru");
+        let data: Vec<u8> = (0..64).collect();
+        let cache = BlockCache::new(&dir, 100).unwrap();
+
+        for id in 1..=3 {
+            let mut f = cache.open(id, io::Cursor::new(data.clone())).unwrap();
+            f.read_to_end(&mut Vec::new()).unwrap();
+        }
+        let state = cache.state.lock().unwrap();
+        assert_eq!(vec![&3], state.entries.keys().collect::<Vec<_>>());
+        assert_eq!(64, state.total_bytes);
+        assert!(!state.data_path(1).exists());
+        assert!(!state.data_path(2).exists());
+        drop(state);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_lazily() {
+        let dir = temp_dir("open_lazily");
+        let cache = BlockCache::new(&dir, 1024).unwrap();
+        let f = cache.open(1, io::Cursor::new(vec![0; 64])).unwrap();
+        drop(f);
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+        assert_eq!(0, cache.state.lock().unwrap().entries.len());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remove_orphans() {
+        let dir = temp_dir("remove_orphans");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("1.data"), [0; 64]).unwrap();
+        fs::write(dir.join("2.ranges"), "0 64\n").unwrap();
+        fs::write(dir.join("3.ranges"), "garbage\n").unwrap();
+        fs::write(dir.join("3.data"), [0; 64]).unwrap();
+        fs::write(dir.join("other"), "").unwrap();
+
+        let cache = BlockCache::new(&dir, 1024).unwrap();
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(vec!["other"], names);
+        let state = cache.state.lock().unwrap();
+        assert_eq!(0, state.entries.len());
+        assert_eq!(0, state.total_bytes);
+        drop(state);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_periodically() {
+        let dir = temp_dir("save_periodically");
+        let data = vec![0; SAVE_INTERVAL as usize * 2];
+        let cache = BlockCache::new(&dir, data.len() as u64).unwrap();
+
+        let mut f = cache.open(1, io::Cursor::new(data.clone())).unwrap();
+        let mut buf = vec![0; SAVE_INTERVAL as usize];
+        f.read_exact(&mut buf).unwrap();
+        // The ranges are saved while the file is still open.
+        let other = BlockCache::new(&dir, data.len() as u64).unwrap();
+        let state = other.state.lock().unwrap();
+        assert_eq!(SAVE_INTERVAL, state.entries[&1].size());
+        drop(state);
+        drop(f);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
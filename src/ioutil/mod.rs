@@ -1,4 +1,6 @@
+mod blockcache;
 mod concat;
+mod hash;
 mod lazyopen;
 mod pattern;
 mod readseek;
@@ -7,7 +9,9 @@ mod skip;
 #[allow(unused)]
 mod oprecorder;
 
+pub use self::blockcache::*;
 pub use self::concat::*;
+pub use self::hash::*;
 pub use self::lazyopen::*;
 pub use self::pattern::*;
 pub use self::readseek::*;
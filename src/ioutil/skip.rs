@@ -21,6 +21,14 @@ where
             initial_skip: false,
         }
     }
+
+    fn skip_once(&mut self) -> io::Result<()> {
+        if !self.initial_skip {
+            self.inner.seek(io::SeekFrom::Start(self.offset))?;
+            self.initial_skip = true;
+        }
+        Ok(())
+    }
 }
 
 impl<T> io::Read for Skip<T>
@@ -28,10 +36,7 @@ where
     T: io::Read + io::Seek,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if !self.initial_skip {
-            self.inner.seek(io::SeekFrom::Start(self.offset))?;
-            self.initial_skip = true;
-        }
+        self.skip_once()?;
         self.inner.read(buf)
     }
 }
@@ -42,6 +47,7 @@ where
 {
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
         // TODO: prevent seeking before self.offset.
+        self.skip_once()?;
         let new_pos = match pos {
             io::SeekFrom::Start(offset) => io::SeekFrom::Start(self.offset + offset),
             io::SeekFrom::End(offset) => io::SeekFrom::End(offset),
@@ -54,7 +60,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Read;
+    use std::io::{Read, Seek};
 
     #[test]
     fn skip() {
@@ -69,4 +75,15 @@ mod tests {
             &[8, 9, 10, 11, 12, 13, 14, 15, 0, 0, 0, 0, 0, 0, 0, 0]
         );
     }
+
+    #[test]
+    fn seek_before_read() {
+        let file: Vec<u8> = (0..16).collect();
+        let mut skip = Skip::new(io::Cursor::new(file), 8);
+
+        skip.seek(io::SeekFrom::Start(4)).unwrap();
+        let mut buf = [0; 4];
+        skip.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &[12, 13, 14, 15]);
+    }
 }
@@ -3,18 +3,29 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod config;
 mod filesystem;
+mod fixture;
 mod id3tag;
 mod ioutil;
 mod mapping;
 mod mp3;
 mod soundcloud;
+mod template;
 
+use self::config::Config;
 use self::filesystem::*;
+use self::fixture::Fixture;
 use self::mapping::*;
+use chrono::Utc;
 use log::*;
 use std::ffi::OsStr;
+use std::fmt;
+use std::path::PathBuf;
 use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 fn main() {
     env_logger::init();
@@ -28,16 +39,21 @@ fn main() {
                 .short("p")
                 .long("path")
                 .takes_value(true)
-                .required(true)
                 .help("Sets the target directory of the mount"),
+        ).arg(
+            clap::Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("path")
+                .takes_value(true)
+                .help("Loads settings from a TOML file, defaults to $XDG_CONFIG_HOME/soundcloud-fs/config.toml. Flags take precedence over the file"),
         ).arg(
             clap::Arg::with_name("user")
                 .short("u")
                 .long("user")
                 .takes_value(true)
-                .required(true)
                 .multiple(true)
-                .help("Sets the user to create directory and file entries for"),
+                .help("Sets the user to create directory and file entries for. Replaces the users in the configuration file"),
         ).arg(
             clap::Arg::with_name("login")
                 .long("login")
@@ -46,7 +62,7 @@ fn main() {
                 .validator(|s| match s.splitn(2, ':').count() {
                     2 => Ok(()),
                     c => Err(format!("bad credential format, split on : yields {} strings", c)),
-                }).help("Logs in using a username and password instead of accessing the API anonymously"),
+                }).help("Logs in using a username and password instead of accessing the API anonymously. This adds the me directory with the stream, likes, history and private tracks of the account"),
         ).arg(
             clap::Arg::with_name("mpeg-padding")
                 .long("mpeg-padding")
@@ -71,13 +87,169 @@ fn main() {
                 .default_value("1")
                 .possible_values(&["0", "1"])
                 .help("Looks into common patterns in track metadata to attempt to determine more accurate ID3 metadata"),
+        ).arg(
+            clap::Arg::with_name("cache-ttl")
+                .long("cache-ttl")
+                .value_name("seconds")
+                .takes_value(true)
+                .default_value("3600")
+                .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets how long directory listings are cached before they are refreshed"),
+        ).arg(
+            clap::Arg::with_name("cache-negative-ttl")
+                .long("cache-negative-ttl")
+                .value_name("seconds")
+                .takes_value(true)
+                .default_value("60")
+                .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets how long the absence of a file is cached"),
+        ).arg(
+            clap::Arg::with_name("cache-refresh-background")
+                .long("cache-refresh-background")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Serves stale directory listings while they are being refreshed in the background"),
+        ).arg(
+            clap::Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .value_name("path")
+                .takes_value(true)
+                .help("Sets the directory for persistent caches, defaults to $XDG_CACHE_HOME/soundcloud-fs"),
+        ).arg(
+            clap::Arg::with_name("metadata-cache")
+                .long("metadata-cache")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Stores API responses in the cache directory so they survive remounts. Entries expire after the cache TTL. Only public responses to anonymous clients are stored"),
+        ).arg(
+            clap::Arg::with_name("persistent-inodes")
+                .long("persistent-inodes")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Stores the assigned inode numbers in the cache directory so they survive remounts"),
+        ).arg(
+            clap::Arg::with_name("audio-cache-size")
+                .long("audio-cache-size")
+                .value_name("MiB")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Stores up to the specified amount of downloaded audio in the cache directory. 0 disables the audio cache"),
+        ).arg(
+            clap::Arg::with_name("read-ahead")
+                .long("read-ahead")
+                .value_name("KiB")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Fetches the specified amount of audio ahead of the reader in the background. 0 disables read-ahead"),
+        ).arg(
+            clap::Arg::with_name("resume-retries")
+                .long("resume-retries")
+                .value_name("count")
+                .takes_value(true)
+                .default_value("5")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets how many times an interrupted audio download is resumed before the read fails"),
+        ).arg(
+            clap::Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .value_name("requests/s")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<f64>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Limits the number of requests per second made to SoundCloud. 0 disables the limit"),
+        ).arg(
+            clap::Arg::with_name("max-requests")
+                .long("max-requests")
+                .value_name("count")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Limits the number of requests to SoundCloud that are in flight at the same time. 0 disables the limit"),
+        ).arg(
+            clap::Arg::with_name("search-limit")
+                .long("search-limit")
+                .value_name("count")
+                .takes_value(true)
+                .default_value("50")
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets the maximum number of tracks, users and playlists that are listed for a search in the search directory"),
+        ).arg(
+            clap::Arg::with_name("fixture")
+                .long("fixture")
+                .value_name("path")
+                .takes_value(true)
+                .help("Mounts the tree described by a JSON fixture instead of SoundCloud, for testing"),
+        ).arg(
+            clap::Arg::with_name("threads")
+                .long("threads")
+                .value_name("count")
+                .takes_value(true)
+                .default_value("8")
+                .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets the number of threads that handle filesystem requests concurrently"),
         ).get_matches();
 
-    let login = cli.value_of("login").and_then(|s| {
-        let mut i = s.splitn(2, ':');
-        let u = i.next().unwrap();
-        i.next().map(|p| (u, p))
-    });
+    let config = load_config(&cli);
+
+    if let Some(path) = cli.value_of("fixture") {
+        let fixture = match Fixture::open(path) {
+            Ok(v) => v,
+            Err(err) => {
+                error!("could not load fixture {}: {}", path, err);
+                process::exit(1);
+            }
+        };
+        mount(
+            &cli,
+            &config,
+            &CacheRoot::new(&fixture, cache_config(&cli, &config)),
+        );
+        return;
+    }
+
+    let users: Vec<UserOptions> = match cli.values_of("user") {
+        Some(names) => names.map(|name| config.user(name)).collect(),
+        None => config.users.clone(),
+    };
+    if users.is_empty() {
+        error!("no users specified, please set --user or add a [[user]] to the configuration");
+        process::exit(1);
+    }
+    if let Some(user) = users
+        .iter()
+        .find(|u| RESERVED_NAMES.contains(&u.name.as_str()))
+    {
+        error!(
+            "{:?} can not be used as a user, the name is reserved",
+            user.name
+        );
+        process::exit(1);
+    }
+
+    soundcloud::set_rate_limit(
+        cli.value_of("rate-limit").unwrap().parse().unwrap(),
+        cli.value_of("max-requests").unwrap().parse().unwrap(),
+    );
+
+    let login = cli
+        .value_of("login")
+        .and_then(|s| {
+            let mut i = s.splitn(2, ':');
+            let u = i.next().unwrap();
+            i.next().map(|p| (u, p))
+        })
+        .or_else(|| {
+            let login = config.login.as_ref()?;
+            Some((login.username.as_str(), login.password.as_str()))
+        });
     let sc_client_rs = match login {
         None => {
             info!("creating anonymous client");
@@ -89,7 +261,7 @@ fn main() {
         }
     };
 
-    let sc_client = match sc_client_rs {
+    let mut sc_client = match sc_client_rs {
         Ok(v) => v,
         Err(err) => {
             error!("could not initialize SoundCloud client: {}", err);
@@ -97,19 +269,176 @@ fn main() {
         }
     };
 
-    let root = RootState {
+    let read_ahead: u64 = cli.value_of("read-ahead").unwrap().parse().unwrap();
+    let resume_retries: u32 = cli.value_of("resume-retries").unwrap().parse().unwrap();
+    sc_client = sc_client
+        .with_read_ahead(read_ahead * 1024)
+        .with_resume_retries(resume_retries);
+
+    let cache_config = cache_config(&cli, &config);
+    let cache_dir = cache_dir(&cli, &config);
+
+    if flag(&cli, "metadata-cache", config.cache.metadata) {
+        let dir = match &cache_dir {
+            Some(v) => v.join("metadata"),
+            None => {
+                error!("could not determine the cache directory, please set --cache-dir");
+                process::exit(1);
+            }
+        };
+        match soundcloud::DiskCache::new(&dir, cache_config.ttl) {
+            Ok(cache) => sc_client = sc_client.with_cache(cache),
+            Err(err) => {
+                error!("could not open cache {}: {}", dir.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let audio_cache_size: u64 = value(&cli, "audio-cache-size", config.cache.audio_size);
+    let audio_cache = if audio_cache_size > 0 {
+        let dir = match &cache_dir {
+            Some(v) => v.join("audio"),
+            None => {
+                error!("could not determine the cache directory, please set --cache-dir");
+                process::exit(1);
+            }
+        };
+        match BlockCache::new(&dir, audio_cache_size * 1024 * 1024) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                error!("could not open cache {}: {}", dir.display(), err);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let root = Arc::new(RootState {
         sc_client,
-        show: cli.values_of("user").unwrap().map(str::to_string).collect(),
-        mpeg_padding: cli.value_of("mpeg-padding") == Some("1"),
-        id3_download_images: cli.value_of("id3-images") == Some("1"),
-        id3_parse_strings: cli.value_of("id3-parse-strings") == Some("1"),
+        users,
+        filenames: config.filenames.clone(),
+        search_limit: value(&cli, "search-limit", config.search_limit),
+        mpeg_padding: flag(&cli, "mpeg-padding", config.mpeg_padding),
+        id3_download_images: flag(&cli, "id3-images", config.id3.images),
+        id3_parse_strings: flag(&cli, "id3-parse-strings", config.id3.parse_strings),
+        audio_cache,
+        derived_depths: Mutex::default(),
+        derived_depths_ttl: cache_config.ttl,
+        me_id: Mutex::default(),
+        mounted_at: Utc::now(),
+    });
+
+    mount(
+        &cli,
+        &config,
+        &CacheRoot::new(&Root::new(root), cache_config),
+    );
+}
+
+/// Loads the configuration file. A missing file at the default location is not an error.
+fn load_config(cli: &clap::ArgMatches) -> Config {
+    let path = match cli.value_of("config") {
+        Some(v) => PathBuf::from(v),
+        None => match Config::default_path() {
+            Some(v) if v.exists() => v,
+            _ => return Config::default(),
+        },
     };
+    match Config::open(&path) {
+        Ok(v) => {
+            info!("loaded configuration from {}", path.display());
+            v
+        }
+        Err(err) => {
+            error!("could not load configuration {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Returns the value of a flag if it was set explicitly, the value from the configuration file if
+/// present or the default of the flag otherwise.
+fn value<T>(cli: &clap::ArgMatches, name: &str, config: Option<T>) -> T
+where
+    T: FromStr,
+    T::Err: fmt::Debug,
+{
+    match config {
+        Some(v) if cli.occurrences_of(name) == 0 => v,
+        _ => cli.value_of(name).unwrap().parse().unwrap(),
+    }
+}
+
+/// Like value, but for the flags that are enabled with 1.
+fn flag(cli: &clap::ArgMatches, name: &str, config: Option<bool>) -> bool {
+    match config {
+        Some(v) if cli.occurrences_of(name) == 0 => v,
+        _ => cli.value_of(name) == Some("1"),
+    }
+}
 
+fn cache_config(cli: &clap::ArgMatches, config: &Config) -> CacheConfig {
+    CacheConfig {
+        ttl: Duration::from_secs(value(cli, "cache-ttl", config.cache.ttl)),
+        negative_ttl: Duration::from_secs(value(
+            cli,
+            "cache-negative-ttl",
+            config.cache.negative_ttl,
+        )),
+        refresh_in_background: flag(
+            cli,
+            "cache-refresh-background",
+            config.cache.refresh_background,
+        ),
+    }
+}
+
+fn cache_dir(cli: &clap::ArgMatches, config: &Config) -> Option<PathBuf> {
+    cli.value_of("cache-dir")
+        .map(PathBuf::from)
+        .or_else(|| config.cache.dir.clone())
+        .or_else(soundcloud::DiskCache::default_dir)
+}
+
+fn mount<N: NodeType>(cli: &clap::ArgMatches, config: &Config, root: &N) {
     let uid = nix::unistd::Uid::current().as_raw() as u32;
     let gid = nix::unistd::Gid::current().as_raw() as u32;
 
-    let fs = FS::new(&CacheRoot::new(&Root::new(&root)), uid, gid);
-    let path = cli.value_of("path").unwrap();
+    let num_threads = cli.value_of("threads").unwrap().parse().unwrap();
+
+    let inodes = if flag(cli, "persistent-inodes", config.cache.persistent_inodes) {
+        let path = match cache_dir(cli, config) {
+            Some(v) => v.join("inodes"),
+            None => {
+                error!("could not determine the cache directory, please set --cache-dir");
+                process::exit(1);
+            }
+        };
+        match InodeTable::open(&path) {
+            Ok(v) => v,
+            Err(err) => {
+                error!("could not open inode table {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    } else {
+        InodeTable::new()
+    };
+
+    let fs = FS::new(root, inodes, uid, gid, num_threads);
+    let path = match cli.value_of("path").map(PathBuf::from) {
+        Some(v) => v,
+        None => match &config.path {
+            Some(v) => v.clone(),
+            None => {
+                error!("no mount path specified, please set --path");
+                process::exit(1);
+            }
+        },
+    };
     let options = &[OsStr::new("-oallow_other"), OsStr::new("-oauto_unmount")];
     fuse::mount(fs, &path, options).unwrap();
+    info!("read-ahead: {:?}", soundcloud::prefetch_stats());
 }
@@ -1,21 +1,34 @@
 use crate::filesystem;
 use crate::id3tag::tag_for_track;
-use crate::ioutil::{Concat, LazyOpen, ReadSeek, Skip};
+use crate::ioutil::{BlockCache, Concat, LazyOpen, ReadSeek, Skip};
 use crate::mp3;
 use crate::soundcloud;
-use chrono::Utc;
+use crate::template::{Filenames, Key, Template};
+use chrono::{DateTime, Utc};
 use id3;
+use log::*;
+use percent_encoding::percent_decode_str;
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fmt;
 use std::io::{self, Seek};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const PADDING_START: u64 = 500;
 const PADDING_END: u64 = 20;
 
+const XATTR_PREFIX: &str = "user.soundcloud.";
+
+/// The maximum number of items listed of the stream and play history, which are endless.
+const FEED_LIMIT: usize = 200;
+
 #[derive(Debug)]
 pub enum Error {
     ChildNotFound,
+    ReadOnly,
+    NotPermitted,
 
     SoundCloudError(soundcloud::Error),
     IOError(io::Error),
@@ -27,9 +40,22 @@ impl filesystem::Error for Error {
         Error::ChildNotFound
     }
 
+    fn read_only() -> Self {
+        Error::ReadOnly
+    }
+
     fn errno(&self) -> i32 {
         match self {
             Error::ChildNotFound => libc::ENOENT,
+            Error::ReadOnly => libc::EROFS,
+            Error::NotPermitted => libc::EPERM,
+            // Without logging in, the filesystem is read-only.
+            Error::SoundCloudError(soundcloud::Error::Unauthenticated) => libc::EROFS,
+            Error::SoundCloudError(soundcloud::Error::ReqwestError(err))
+                if err.status() == Some(reqwest::StatusCode::NOT_FOUND) =>
+            {
+                libc::ENOENT
+            }
             Error::SoundCloudError(_) => libc::EIO,
             Error::IOError(err) => err.raw_os_error().unwrap_or(libc::EIO),
             Error::ID3Error(_) => libc::EIO,
@@ -38,7 +64,7 @@ impl filesystem::Error for Error {
 }
 
 impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
@@ -63,51 +89,256 @@ impl From<id3::Error> for Error {
     }
 }
 
-// TODO: Use proper lifetimes to share state and make this private.
-#[derive(Clone)]
+/// Controls which entries are created for a user.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct UserOptions {
+    /// The permalink of the user.
+    #[serde(deserialize_with = "user_name")]
+    pub name: String,
+    #[serde(default = "enabled")]
+    pub tracks: bool,
+    #[serde(default = "enabled")]
+    pub favorites: bool,
+    #[serde(default = "enabled")]
+    pub following: bool,
+    /// Lists the users that follow the user. This is disabled by default, because there may be
+    /// a lot of them.
+    #[serde(default)]
+    pub followers: bool,
+    #[serde(default = "enabled")]
+    pub playlists: bool,
+    #[serde(default = "enabled")]
+    pub reposts: bool,
+    /// How deep the subdirectories go. At 0, only the tracks of the user are listed. At 1, the
+    /// favorites, following, playlists and reposts directories are added as well. Users that are followed
+    /// get one level less than the user that follows them, so at 2 the users followed have their
+    /// subdirectories as well.
+    #[serde(default = "default_depth")]
+    pub depth: u32,
+}
+
+impl UserOptions {
+    pub fn new(name: impl Into<String>) -> Self {
+        UserOptions {
+            name: name.into(),
+            tracks: true,
+            favorites: true,
+            following: true,
+            followers: false,
+            playlists: true,
+            reposts: true,
+            depth: default_depth(),
+        }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_depth() -> u32 {
+    1
+}
+
+/// The names of the directories in the root that are not users.
+pub const RESERVED_NAMES: [&str; 3] = ["me", "search", "resolve"];
+
+fn user_name<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name: String = serde::Deserialize::deserialize(deserializer)?;
+    if RESERVED_NAMES.contains(&name.as_str()) {
+        return Err(serde::de::Error::custom(format!(
+            "{:?} can not be used as a user, the name is reserved",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+// TODO: Make this private.
 pub struct RootState {
     pub sc_client: soundcloud::Client,
-    pub show: Vec<String>,
+    /// The users that are listed in the root directory.
+    pub users: Vec<UserOptions>,
+    /// The templates for the names of tracks.
+    pub filenames: Filenames,
+    /// The maximum number of results listed per kind of a search.
+    pub search_limit: usize,
     pub mpeg_padding: bool,
     pub id3_download_images: bool,
     pub id3_parse_strings: bool,
+    pub audio_cache: Option<BlockCache>,
+    /// The depths of the users that are followed by the listed users, see
+    /// RootState::compute_derived_depths. These are computed when first needed.
+    pub derived_depths: Mutex<DerivedDepths>,
+    /// How long the derived depths are used before they are computed again.
+    pub derived_depths_ttl: Duration,
+    /// The id of the user the client is logged in as, which is looked up when first needed.
+    pub me_id: Mutex<Option<i64>>,
+    /// The time of the mount, which is reported for the directories that do not correspond to
+    /// anything on SoundCloud.
+    pub mounted_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct DerivedDepths {
+    depths: Option<(Instant, Arc<HashMap<String, u32>>)>,
+    /// Incremented when the depths are invalidated, so a computation that was already running
+    /// does not store outdated depths.
+    generation: u64,
+}
+
+impl RootState {
+    /// Returns the metadata of a directory that does not correspond to anything on SoundCloud.
+    fn dir_metadata(&self) -> filesystem::Metadata {
+        filesystem::Metadata {
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            perm: 0o555,
+        }
+    }
+
+    /// Returns the id of the user the client is logged in as.
+    fn me_id(&self) -> Result<i64, Error> {
+        if let Some(id) = *self.me_id.lock().unwrap() {
+            return Ok(id);
+        }
+        let id = self.sc_client.me()?.id;
+        *self.me_id.lock().unwrap() = Some(id);
+        Ok(id)
+    }
+
+    /// Returns the options of a user, which are the configured ones if the user is listed.
+    fn user_options(&self, name: &str) -> UserOptions {
+        match self.users.iter().find(|u| u.name == name) {
+            Some(options) => options.clone(),
+            None => self.derived_options(name),
+        }
+    }
+
+    /// Returns the options of a user that is not listed, which are derived from the depth of the
+    /// users that follow it.
+    fn derived_options(&self, name: &str) -> UserOptions {
+        let (cached, generation) = {
+            let state = self.derived_depths.lock().unwrap();
+            let cached = state
+                .depths
+                .as_ref()
+                .filter(|(at, _)| at.elapsed() < self.derived_depths_ttl)
+                .map(|(_, depths)| depths.clone());
+            (cached, state.generation)
+        };
+        // The lock is not held while computing, so other lookups are not held up by it.
+        let depths = cached.unwrap_or_else(|| {
+            let depths = Arc::new(self.compute_derived_depths());
+            let mut state = self.derived_depths.lock().unwrap();
+            if state.generation == generation {
+                state.depths = Some((Instant::now(), depths.clone()));
+            }
+            depths
+        });
+        let depth = depths.get(name).cloned().unwrap_or(0);
+        UserOptions {
+            depth,
+            ..UserOptions::new(name)
+        }
+    }
+
+    /// Computes the depths of the users that are followed by the listed users. Each level of
+    /// depth requires the followings of all users on that level to be fetched, so the result is
+    /// kept for as long as directory listings are. Users of which the followings can not be
+    /// fetched are skipped.
+    fn compute_derived_depths(&self) -> HashMap<String, u32> {
+        let client = &self.sc_client;
+        let mut depths = HashMap::new();
+
+        let mut seen: HashSet<String> = self.users.iter().map(|u| u.name.clone()).collect();
+        let mut level: Vec<(String, u32)> = self
+            .users
+            .iter()
+            .filter(|u| u.following && u.depth > 1)
+            .map(|u| (u.name.clone(), u.depth))
+            .collect();
+        while !level.is_empty() {
+            let mut next = Vec::new();
+            for (follower, depth) in level {
+                let followings = soundcloud::User::by_name(client, &follower)
+                    .and_then(|user| user.following(client));
+                let followings = match followings {
+                    Ok(v) => v,
+                    Err(err) => {
+                        warn!("could not get the users followed by {}: {}", follower, err);
+                        continue;
+                    }
+                };
+                for user in followings {
+                    let d = depths.entry(user.permalink.clone()).or_insert(0);
+                    *d = (*d).max(depth - 1);
+                    if depth > 2 && seen.insert(user.permalink.clone()) {
+                        next.push((user.permalink, depth - 1));
+                    }
+                }
+            }
+            level = next;
+        }
+        depths
+    }
+
+    /// Drops the derived depths after the followings of a user have changed.
+    fn invalidate_derived_depths(&self) {
+        let mut state = self.derived_depths.lock().unwrap();
+        state.depths = None;
+        state.generation += 1;
+    }
 }
 
 #[derive(Clone)]
-pub struct Root<'a> {
-    inner: &'a RootState,
+pub struct Root {
+    inner: Arc<RootState>,
 }
 
-impl<'a> Root<'a> {
-    pub fn new(inner: &'a RootState) -> Self {
+impl Root {
+    pub fn new(inner: Arc<RootState>) -> Self {
         Root { inner }
     }
 }
 
-impl<'a> filesystem::NodeType for Root<'a> {
+impl filesystem::NodeType for Root {
     type Error = Error;
-    type File = TrackAudio<'a>;
-    type Directory = Dir<'a>;
-    type Symlink = UserReference;
+    type File = TrackAudio;
+    type Directory = Dir;
+    type Symlink = Link;
 
     fn root(&self) -> Self::Directory {
-        Dir::UserList(UserList { inner: &self.inner })
+        Dir::UserList(UserList {
+            inner: self.inner.clone(),
+        })
     }
 }
 
-// Clippy complains about a large size difference in the enum variants. This is because the
-// UserList is the only variant that does not has a user field. The warning has been silenced
-// because the UserList variant will only be instantiated once.
-#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
-pub enum Dir<'a> {
-    UserList(UserList<'a>),
-    UserProfile(UserProfile<'a>),
-    UserFavorites(UserFavorites<'a>),
-    UserFollowing(UserFollowing<'a>),
+pub enum Dir {
+    UserList(UserList),
+    UserProfile(Box<UserProfile>),
+    UserFavorites(Box<UserFavorites>),
+    UserFollowing(Box<UserFollowing>),
+    UserFollowers(Box<UserFollowers>),
+    UserPlaylists(Box<UserPlaylists>),
+    UserReposts(Box<UserReposts>),
+    Playlist(PlaylistTracks),
+    Me(MeDir),
+    MeFeed(MeFeed),
+    SearchRoot(SearchRoot),
+    Search(SearchResults),
+    SearchUsers(SearchUsers),
+    SearchPlaylists(SearchPlaylists),
+    Resolve(Box<ResolveDir>),
 }
 
-impl filesystem::Meta for Dir<'_> {
+impl filesystem::Meta for Dir {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
         match self {
@@ -115,21 +346,83 @@ impl filesystem::Meta for Dir<'_> {
             Dir::UserProfile(f) => f.metadata(),
             Dir::UserFavorites(f) => f.metadata(),
             Dir::UserFollowing(f) => f.metadata(),
+            Dir::UserFollowers(f) => f.metadata(),
+            Dir::UserPlaylists(f) => f.metadata(),
+            Dir::UserReposts(f) => f.metadata(),
+            Dir::Playlist(f) => f.metadata(),
+            Dir::Me(f) => f.metadata(),
+            Dir::MeFeed(f) => f.metadata(),
+            Dir::SearchRoot(f) => f.metadata(),
+            Dir::Search(f) => f.metadata(),
+            Dir::SearchUsers(f) => f.metadata(),
+            Dir::SearchPlaylists(f) => f.metadata(),
+            Dir::Resolve(f) => f.metadata(),
+        }
+    }
+
+    fn id(&self) -> Option<String> {
+        match self {
+            Dir::UserList(f) => f.id(),
+            Dir::UserProfile(f) => f.id(),
+            Dir::UserFavorites(f) => f.id(),
+            Dir::UserFollowing(f) => f.id(),
+            Dir::UserFollowers(f) => f.id(),
+            Dir::UserPlaylists(f) => f.id(),
+            Dir::UserReposts(f) => f.id(),
+            Dir::Playlist(f) => f.id(),
+            Dir::Me(f) => f.id(),
+            Dir::MeFeed(f) => f.id(),
+            Dir::SearchRoot(f) => f.id(),
+            Dir::Search(f) => f.id(),
+            Dir::SearchUsers(f) => f.id(),
+            Dir::SearchPlaylists(f) => f.id(),
+            Dir::Resolve(f) => f.id(),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.xattrs(),
+            Dir::UserProfile(f) => f.xattrs(),
+            Dir::UserFavorites(f) => f.xattrs(),
+            Dir::UserFollowing(f) => f.xattrs(),
+            Dir::UserFollowers(f) => f.xattrs(),
+            Dir::UserPlaylists(f) => f.xattrs(),
+            Dir::UserReposts(f) => f.xattrs(),
+            Dir::Playlist(f) => f.xattrs(),
+            Dir::Me(f) => f.xattrs(),
+            Dir::MeFeed(f) => f.xattrs(),
+            Dir::SearchRoot(f) => f.xattrs(),
+            Dir::Search(f) => f.xattrs(),
+            Dir::SearchUsers(f) => f.xattrs(),
+            Dir::SearchPlaylists(f) => f.xattrs(),
+            Dir::Resolve(f) => f.xattrs(),
         }
     }
 }
 
-impl<'a> filesystem::Directory<Root<'a>> for Dir<'a> {
-    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
+impl filesystem::Directory<Root> for Dir {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
         match self {
             Dir::UserList(f) => f.files(),
             Dir::UserProfile(f) => f.files(),
             Dir::UserFavorites(f) => f.files(),
             Dir::UserFollowing(f) => f.files(),
+            Dir::UserFollowers(f) => f.files(),
+            Dir::UserPlaylists(f) => f.files(),
+            Dir::UserReposts(f) => f.files(),
+            Dir::Playlist(f) => f.files(),
+            Dir::Me(f) => f.files(),
+            Dir::MeFeed(f) => f.files(),
+            Dir::SearchRoot(f) => f.files(),
+            Dir::Search(f) => f.files(),
+            Dir::SearchUsers(f) => f.files(),
+            Dir::SearchPlaylists(f) => f.files(),
+            Dir::Resolve(f) => f.files(),
         }
     }
 
-    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
         if !is_valid_file(name) {
             return Err(Error::ChildNotFound);
         }
@@ -138,63 +431,211 @@ impl<'a> filesystem::Directory<Root<'a>> for Dir<'a> {
             Dir::UserProfile(f) => f.file_by_name(name),
             Dir::UserFavorites(f) => f.file_by_name(name),
             Dir::UserFollowing(f) => f.file_by_name(name),
+            Dir::UserFollowers(f) => f.file_by_name(name),
+            Dir::UserPlaylists(f) => f.file_by_name(name),
+            Dir::UserReposts(f) => f.file_by_name(name),
+            Dir::Playlist(f) => f.file_by_name(name),
+            Dir::Me(f) => f.file_by_name(name),
+            Dir::MeFeed(f) => f.file_by_name(name),
+            Dir::SearchRoot(f) => f.file_by_name(name),
+            Dir::Search(f) => f.file_by_name(name),
+            Dir::SearchUsers(f) => f.file_by_name(name),
+            Dir::SearchPlaylists(f) => f.file_by_name(name),
+            Dir::Resolve(f) => f.file_by_name(name),
+        }
+    }
+
+    fn create(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.create(name),
+            Dir::UserProfile(f) => f.create(name),
+            Dir::UserFavorites(f) => f.create(name),
+            Dir::UserFollowing(f) => f.create(name),
+            Dir::UserFollowers(f) => f.create(name),
+            Dir::UserPlaylists(f) => f.create(name),
+            Dir::UserReposts(f) => f.create(name),
+            Dir::Playlist(f) => f.create(name),
+            Dir::Me(f) => f.create(name),
+            Dir::MeFeed(f) => f.create(name),
+            Dir::SearchRoot(f) => f.create(name),
+            Dir::Search(f) => f.create(name),
+            Dir::SearchUsers(f) => f.create(name),
+            Dir::SearchPlaylists(f) => f.create(name),
+            Dir::Resolve(f) => f.create(name),
+        }
+    }
+
+    fn link(
+        &self,
+        name: &str,
+        node: &filesystem::Node<Root>,
+    ) -> Result<filesystem::Node<Root>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.link(name, node),
+            Dir::UserProfile(f) => f.link(name, node),
+            Dir::UserFavorites(f) => f.link(name, node),
+            Dir::UserFollowing(f) => f.link(name, node),
+            Dir::UserFollowers(f) => f.link(name, node),
+            Dir::UserPlaylists(f) => f.link(name, node),
+            Dir::UserReposts(f) => f.link(name, node),
+            Dir::Playlist(f) => f.link(name, node),
+            Dir::Me(f) => f.link(name, node),
+            Dir::MeFeed(f) => f.link(name, node),
+            Dir::SearchRoot(f) => f.link(name, node),
+            Dir::Search(f) => f.link(name, node),
+            Dir::SearchUsers(f) => f.link(name, node),
+            Dir::SearchPlaylists(f) => f.link(name, node),
+            Dir::Resolve(f) => f.link(name, node),
+        }
+    }
+
+    fn mkdir(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.mkdir(name),
+            Dir::UserProfile(f) => f.mkdir(name),
+            Dir::UserFavorites(f) => f.mkdir(name),
+            Dir::UserFollowing(f) => f.mkdir(name),
+            Dir::UserFollowers(f) => f.mkdir(name),
+            Dir::UserPlaylists(f) => f.mkdir(name),
+            Dir::UserReposts(f) => f.mkdir(name),
+            Dir::Playlist(f) => f.mkdir(name),
+            Dir::Me(f) => f.mkdir(name),
+            Dir::MeFeed(f) => f.mkdir(name),
+            Dir::SearchRoot(f) => f.mkdir(name),
+            Dir::Search(f) => f.mkdir(name),
+            Dir::SearchUsers(f) => f.mkdir(name),
+            Dir::SearchPlaylists(f) => f.mkdir(name),
+            Dir::Resolve(f) => f.mkdir(name),
+        }
+    }
+
+    fn symlink(&self, name: &str, target: &Path) -> Result<filesystem::Node<Root>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.symlink(name, target),
+            Dir::UserProfile(f) => f.symlink(name, target),
+            Dir::UserFavorites(f) => f.symlink(name, target),
+            Dir::UserFollowing(f) => f.symlink(name, target),
+            Dir::UserFollowers(f) => f.symlink(name, target),
+            Dir::UserPlaylists(f) => f.symlink(name, target),
+            Dir::UserReposts(f) => f.symlink(name, target),
+            Dir::Playlist(f) => f.symlink(name, target),
+            Dir::Me(f) => f.symlink(name, target),
+            Dir::MeFeed(f) => f.symlink(name, target),
+            Dir::SearchRoot(f) => f.symlink(name, target),
+            Dir::Search(f) => f.symlink(name, target),
+            Dir::SearchUsers(f) => f.symlink(name, target),
+            Dir::SearchPlaylists(f) => f.symlink(name, target),
+            Dir::Resolve(f) => f.symlink(name, target),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        match self {
+            Dir::UserList(f) => f.unlink(name),
+            Dir::UserProfile(f) => f.unlink(name),
+            Dir::UserFavorites(f) => f.unlink(name),
+            Dir::UserFollowing(f) => f.unlink(name),
+            Dir::UserFollowers(f) => f.unlink(name),
+            Dir::UserPlaylists(f) => f.unlink(name),
+            Dir::UserReposts(f) => f.unlink(name),
+            Dir::Playlist(f) => f.unlink(name),
+            Dir::Me(f) => f.unlink(name),
+            Dir::MeFeed(f) => f.unlink(name),
+            Dir::SearchRoot(f) => f.unlink(name),
+            Dir::Search(f) => f.unlink(name),
+            Dir::SearchUsers(f) => f.unlink(name),
+            Dir::SearchPlaylists(f) => f.unlink(name),
+            Dir::Resolve(f) => f.unlink(name),
         }
     }
 }
 
 #[derive(Clone)]
-pub struct UserList<'a> {
-    inner: &'a RootState,
+pub struct UserList {
+    inner: Arc<RootState>,
 }
 
-impl filesystem::Meta for UserList<'_> {
+impl filesystem::Meta for UserList {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
-        let now = Utc::now();
-        Ok(filesystem::Metadata {
-            mtime: now,
-            ctime: now,
-            perm: 0o555,
-        })
+        Ok(self.inner.dir_metadata())
     }
 }
 
-impl<'a> filesystem::Directory<Root<'a>> for UserList<'a> {
-    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
-        self.inner
-            .show
-            .iter()
-            .map(|name| {
-                let entry = filesystem::Node::Directory(Dir::UserProfile(UserProfile {
-                    inner: &self.inner,
-                    user: soundcloud::User::by_name(&self.inner.sc_client, name)?,
-                    recurse: true,
-                }));
-                Ok((name.clone(), entry))
-            })
-            .collect()
+impl UserList {
+    fn resolve(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::Resolve(Box::new(ResolveDir {
+            inner: self.inner.clone(),
+            prefix: Vec::new(),
+            profile: None,
+        })))
     }
 
-    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+    fn search(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::SearchRoot(SearchRoot {
+            inner: self.inner.clone(),
+        }))
+    }
+
+    fn me(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::Me(MeDir {
+            inner: self.inner.clone(),
+        }))
+    }
+}
+
+impl filesystem::Directory<Root> for UserList {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let mut files = Vec::new();
+        if self.inner.sc_client.is_authenticated() {
+            files.push(("me".to_string(), self.me()));
+        }
+        files.push(("search".to_string(), self.search()));
+        files.push(("resolve".to_string(), self.resolve()));
+        for options in &self.inner.users {
+            let entry = filesystem::Node::Directory(Dir::UserProfile(Box::new(UserProfile {
+                inner: self.inner.clone(),
+                user: soundcloud::User::by_name(&self.inner.sc_client, &options.name)?,
+                options: options.clone(),
+            })));
+            files.push((options.name.clone(), entry));
+        }
+        Ok(files)
+    }
+
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        // The me, search and resolve directories shadow the users with those names, if there are
+        // any.
+        if name == "me" && self.inner.sc_client.is_authenticated() {
+            return Ok(self.me());
+        }
+        if name == "search" {
+            return Ok(self.search());
+        }
+        if name == "resolve" {
+            return Ok(self.resolve());
+        }
         if name.contains('.') {
             return Err(Error::ChildNotFound);
         }
-        let entry = filesystem::Node::Directory(Dir::UserProfile(UserProfile {
-            inner: &self.inner,
-            user: soundcloud::User::by_name(&self.inner.sc_client, name)?,
-            recurse: self.inner.show.iter().any(|n| n == name),
-        }));
+        let user = soundcloud::User::by_name(&self.inner.sc_client, name)?;
+        let options = self.inner.user_options(name);
+        let entry = filesystem::Node::Directory(Dir::UserProfile(Box::new(UserProfile {
+            inner: self.inner.clone(),
+            user,
+            options,
+        })));
         Ok(entry)
     }
 }
 
 #[derive(Clone)]
-pub struct UserFavorites<'a> {
-    inner: &'a RootState,
+pub struct UserFavorites {
+    inner: Arc<RootState>,
     user: soundcloud::User,
 }
 
-impl filesystem::Meta for UserFavorites<'_> {
+impl filesystem::Meta for UserFavorites {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
         Ok(filesystem::Metadata {
@@ -203,35 +644,120 @@ impl filesystem::Meta for UserFavorites<'_> {
             perm: 0o555,
         })
     }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("user:{}:favorites", self.user.id))
+    }
 }
 
-impl<'a> filesystem::Directory<Root<'a>> for UserFavorites<'a> {
-    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
-        let files: Vec<_> = self
-            .user
-            .favorites(&self.inner.sc_client)?
-            .into_iter()
-            .map(|track| {
-                (
-                    format!("{}_-_{}.mp3", track.user.permalink, track.permalink),
-                    filesystem::Node::File(TrackAudio {
-                        inner: self.inner,
-                        track,
-                    }),
-                )
-            })
-            .collect();
-        Ok(files)
+impl UserFavorites {
+    /// Finds the track referred to by a path, which is either a file in a user directory or an
+    /// entry of a favorites directory. Only the last two components are used, so the path does
+    /// not need to be valid relative to this directory.
+    fn track_by_path(&self, path: &Path) -> Result<soundcloud::Track, Error> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(Error::ChildNotFound)?;
+        let parent = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str());
+        let filenames = &self.inner.filenames;
+        let key = filenames
+            .favorites
+            .parse_name(name)
+            .filter(|key| key.id.is_some() || key.user.is_some() && key.permalink.is_some())
+            .or_else(|| filenames.tracks.parse_name(name))
+            .ok_or(Error::ChildNotFound)?;
+        let track = track_by_key(&self.inner.sc_client, &key, parent)?;
+        // A number in parentheses may have been added to tell duplicate names apart, in which
+        // case it is not necessarily an id.
+        if !filenames.favorites.matches(name, &track) && !filenames.tracks.matches(name, &track) {
+            return Err(Error::ChildNotFound);
+        }
+        Ok(track)
+    }
+
+    /// Links and symlinks must be named like the entry the track is listed as afterwards.
+    fn check_name(&self, name: &str, track: &soundcloud::Track) -> Result<(), Error> {
+        if !self.inner.filenames.favorites.matches(name, track) {
+            return Err(Error::NotPermitted);
+        }
+        Ok(())
+    }
+
+    fn like(&self, track: soundcloud::Track) -> Result<filesystem::Node<Root>, Error> {
+        self.inner.sc_client.like(track.id)?;
+        Ok(filesystem::Node::File(TrackAudio {
+            inner: self.inner.clone(),
+            track,
+            playlist: None,
+        }))
+    }
+}
+
+impl filesystem::Directory<Root> for UserFavorites {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let tracks = self.user.favorites(&self.inner.sc_client)?;
+        Ok(track_files(
+            &self.inner,
+            &self.inner.filenames.favorites,
+            tracks,
+        ))
+    }
+
+    fn create(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        check_own(&self.inner, &self.user)?;
+        let track = self.track_by_path(Path::new(name))?;
+        self.like(track)
+    }
+
+    fn link(
+        &self,
+        name: &str,
+        node: &filesystem::Node<Root>,
+    ) -> Result<filesystem::Node<Root>, Self::Error> {
+        check_own(&self.inner, &self.user)?;
+        match node {
+            filesystem::Node::File(audio) => {
+                self.check_name(name, &audio.track)?;
+                self.like(audio.track.clone())
+            }
+            _ => Err(Error::NotPermitted),
+        }
+    }
+
+    /// Likes the track the target points to. The link must have the name the track is listed
+    /// under, as the track is listed as a file rather than as a link.
+    fn symlink(&self, name: &str, target: &Path) -> Result<filesystem::Node<Root>, Self::Error> {
+        check_own(&self.inner, &self.user)?;
+        let track = self.track_by_path(target)?;
+        self.check_name(name, &track)?;
+        let mtime = track.last_modified;
+        self.like(track)?;
+        Ok(filesystem::Node::Symlink(Link::Track(TrackReference {
+            target: target.to_path_buf(),
+            mtime,
+        })))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        check_own(&self.inner, &self.user)?;
+        match self.file_by_name(name)? {
+            filesystem::Node::File(audio) => Ok(self.inner.sc_client.unlike(audio.track.id)?),
+            _ => Err(Error::NotPermitted),
+        }
     }
 }
 
 #[derive(Clone)]
-pub struct UserFollowing<'a> {
-    inner: &'a RootState,
+pub struct UserFollowing {
+    inner: Arc<RootState>,
     user: soundcloud::User,
 }
 
-impl filesystem::Meta for UserFollowing<'_> {
+impl filesystem::Meta for UserFollowing {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
         Ok(filesystem::Metadata {
@@ -240,10 +766,30 @@ impl filesystem::Meta for UserFollowing<'_> {
             perm: 0o555,
         })
     }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("user:{}:following", self.user.id))
+    }
+}
+
+impl UserFollowing {
+    /// Follows a user and returns the link that is listed for them under the name.
+    fn follow(&self, name: &str, permalink: &str) -> Result<filesystem::Node<Root>, Error> {
+        check_own(&self.inner, &self.user)?;
+        let user = soundcloud::User::by_name(&self.inner.sc_client, permalink)?;
+        if name != user.permalink {
+            return Err(Error::NotPermitted);
+        }
+        user.follow(&self.inner.sc_client)?;
+        self.inner.invalidate_derived_depths();
+        Ok(filesystem::Node::Symlink(Link::User(Box::new(
+            UserReference { user, depth: 2 },
+        ))))
+    }
 }
 
-impl<'a> filesystem::Directory<Root<'a>> for UserFollowing<'a> {
-    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
+impl filesystem::Directory<Root> for UserFollowing {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
         let files: Vec<_> = self
             .user
             .following(&self.inner.sc_client)?
@@ -251,39 +797,53 @@ impl<'a> filesystem::Directory<Root<'a>> for UserFollowing<'a> {
             .map(|user| {
                 (
                     user.permalink.clone(),
-                    filesystem::Node::Symlink(UserReference { user }),
+                    filesystem::Node::Symlink(Link::User(Box::new(UserReference {
+                        user,
+                        depth: 2,
+                    }))),
                 )
             })
             .collect();
         Ok(files)
     }
-}
 
-#[derive(Clone)]
-pub struct UserProfile<'a> {
-    inner: &'a RootState,
-    user: soundcloud::User,
-    // Only add child directories for users marked for recursing, to prevent recursing too deeply.
-    recurse: bool,
-}
+    /// Follows the user. Afterwards, the user is listed as a symlink to their directory, so that
+    /// link is returned and the directory is not reported as created.
+    fn mkdir(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        self.follow(name, name)
+    }
 
-impl<'a> UserProfile<'a> {
-    fn favorites(&self) -> filesystem::Node<Root<'a>> {
-        filesystem::Node::Directory(Dir::UserFavorites(UserFavorites {
-            user: self.user.clone(),
-            inner: self.inner,
-        }))
+    /// Follows the user the target points to, which is the last component of the target. The
+    /// link must be named after the user.
+    fn symlink(&self, name: &str, target: &Path) -> Result<filesystem::Node<Root>, Self::Error> {
+        let permalink = target
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(Error::ChildNotFound)?;
+        self.follow(name, permalink)
     }
 
-    fn following(&self) -> filesystem::Node<Root<'a>> {
-        filesystem::Node::Directory(Dir::UserFollowing(UserFollowing {
-            inner: self.inner,
-            user: self.user.clone(),
-        }))
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        check_own(&self.inner, &self.user)?;
+        match self.file_by_name(name)? {
+            filesystem::Node::Symlink(Link::User(link)) => {
+                link.user.unfollow(&self.inner.sc_client)?
+            }
+            _ => return Err(Error::NotPermitted),
+        }
+        self.inner.invalidate_derived_depths();
+        Ok(())
     }
 }
 
-impl filesystem::Meta for UserProfile<'_> {
+/// The inverse of UserFollowing. Unlike the following directory, this one can not be modified.
+#[derive(Clone)]
+pub struct UserFollowers {
+    inner: Arc<RootState>,
+    user: soundcloud::User,
+}
+
+impl filesystem::Meta for UserFollowers {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
         Ok(filesystem::Metadata {
@@ -292,167 +852,1689 @@ impl filesystem::Meta for UserProfile<'_> {
             perm: 0o555,
         })
     }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("user:{}:followers", self.user.id))
+    }
 }
 
-impl<'a> filesystem::Directory<Root<'a>> for UserProfile<'a> {
-    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
-        let mut files = Vec::new();
-        if self.recurse {
-            files.push(("favorites".to_string(), self.favorites()));
-            files.push(("following".to_string(), self.following()));
-        }
-        let tracks = self
+impl filesystem::Directory<Root> for UserFollowers {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let files: Vec<_> = self
             .user
-            .tracks(&self.inner.sc_client)?
+            .followers(&self.inner.sc_client)?
             .into_iter()
-            .map(|track| {
+            .map(|user| {
                 (
-                    format!("{}.mp3", track.permalink),
-                    filesystem::Node::File(TrackAudio {
-                        inner: self.inner,
-                        track,
-                    }),
+                    user.permalink.clone(),
+                    filesystem::Node::Symlink(Link::User(Box::new(UserReference {
+                        user,
+                        depth: 2,
+                    }))),
                 )
-            });
-        files.extend(tracks);
+            })
+            .collect();
         Ok(files)
     }
-
-    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
-        match name {
-            "favorites" => return Ok(self.favorites()),
-            "following" => return Ok(self.following()),
-            _ => (),
-        }
-
-        let track_pl = name.trim_end_matches(".mp3");
-        let track =
-            soundcloud::Track::by_permalink(&self.inner.sc_client, &self.user.permalink, track_pl)?;
-        Ok(filesystem::Node::File(TrackAudio {
-            inner: self.inner,
-            track,
-        }))
-    }
 }
 
 #[derive(Clone)]
-pub struct TrackAudio<'a> {
-    inner: &'a RootState,
-    track: soundcloud::Track,
+pub struct UserPlaylists {
+    inner: Arc<RootState>,
+    user: soundcloud::User,
 }
 
-impl filesystem::Meta for TrackAudio<'_> {
+impl filesystem::Meta for UserPlaylists {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
         Ok(filesystem::Metadata {
-            mtime: self.track.last_modified,
-            ctime: self.track.last_modified,
-            perm: 0o444,
+            mtime: self.user.last_modified,
+            ctime: self.user.last_modified,
+            perm: 0o555,
         })
     }
-}
-
-impl<'a> filesystem::File for TrackAudio<'a> {
-    type Reader = Concat<Box<dyn ReadSeek + 'a>>;
-
-    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
-        let id3_tag = tag_for_track(
-            &self.track,
-            self.inner.id3_download_images,
-            self.inner.id3_parse_strings,
-        )?;
-
-        let remote_mp3_size = self.track.audio_size() as u64;
-        let padding_len = mp3::ZERO_FRAME.len() as u64;
-        let mp3_total_size =
-            remote_mp3_size + PADDING_START * padding_len + PADDING_END * padding_len;
-        let mp3_header = mp3::cbr_header(mp3_total_size);
-        let first_frame_size = mp3_header.len() as u64;
-
-        // Hackety hack: the file concatenation abstraction is able to lazily index the
-        // size of the underlying files. This ensures for programs that just want to probe
-        // the audio file's metadata, no request for the actual audio file will be
-        // performed.
-        // However, because reading programs may read beyond the metadata, the audio may
-        // still be accessed. To counter this, we jam a very large swath of zero bytes in
-        // between the metadata and audio stream to saturate the read buffer without the
-        // audio stream.
-        let padding_start = mp3::zero_frames(PADDING_START);
-        // We also need some padding at the end for players that try to
-        // read ID3v1 metadata.
-        let padding_end = mp3::zero_frames(PADDING_END);
-
-        let track_cp = self.track.clone();
-        let sc_client_cp = &self.inner.sc_client;
-        let audio = LazyOpen::with_size_hint(remote_mp3_size, move || {
-            let f = track_cp
-                .audio(sc_client_cp)
-                .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{}", err)))?;
-            Ok(Skip::new(f, first_frame_size))
-        });
 
-        let concat = if self.inner.mpeg_padding {
-            Concat::new(vec![
-                Box::<dyn ReadSeek>::from(Box::new(id3_tag)),
-                Box::<dyn ReadSeek>::from(Box::new(io::Cursor::new(mp3_header))),
-                Box::<dyn ReadSeek>::from(Box::new(padding_start)),
-                Box::<dyn ReadSeek>::from(Box::new(audio)),
-                Box::<dyn ReadSeek>::from(Box::new(padding_end)),
-            ])
-        } else {
-            Concat::new(vec![
-                Box::<dyn ReadSeek>::from(Box::new(id3_tag)),
-                Box::<dyn ReadSeek>::from(Box::new(audio)),
-            ])
-        };
-        Ok(concat)
+    fn id(&self) -> Option<String> {
+        Some(format!("user:{}:playlists", self.user.id))
     }
+}
 
-    fn size(&self) -> Result<u64, Self::Error> {
-        let id3_tag_size = {
-            let mut b = tag_for_track(
-                &self.track,
-                self.inner.id3_download_images,
-                self.inner.id3_parse_strings,
-            )?;
-            b.seek(io::SeekFrom::End(0)).unwrap()
-        };
-        let padding_size = if self.inner.mpeg_padding {
-            let padding_len = mp3::ZERO_FRAME.len() as u64;
-            PADDING_START * padding_len + PADDING_END * padding_len
-        } else {
-            0
-        };
-        Ok(id3_tag_size + padding_size + self.track.audio_size() as u64)
+impl filesystem::Directory<Root> for UserPlaylists {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let files: Vec<_> = self
+            .user
+            .playlists(&self.inner.sc_client)?
+            .into_iter()
+            .map(|playlist| {
+                (
+                    playlist.permalink.clone(),
+                    filesystem::Node::Directory(Dir::Playlist(PlaylistTracks {
+                        inner: self.inner.clone(),
+                        playlist: Arc::new(playlist),
+                    })),
+                )
+            })
+            .collect();
+        Ok(files)
     }
 }
 
 #[derive(Clone)]
-pub struct UserReference {
+pub struct UserReposts {
+    inner: Arc<RootState>,
     user: soundcloud::User,
 }
 
-impl filesystem::Meta for UserReference {
+impl filesystem::Meta for UserReposts {
     type Error = Error;
     fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
         Ok(filesystem::Metadata {
             mtime: self.user.last_modified,
             ctime: self.user.last_modified,
-            perm: 0o444,
+            perm: 0o555,
         })
     }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("user:{}:reposts", self.user.id))
+    }
 }
 
-impl filesystem::Symlink for UserReference {
-    fn read_link(&self) -> Result<PathBuf, Self::Error> {
-        Ok(["..", "..", &self.user.permalink].iter().collect())
+impl filesystem::Directory<Root> for UserReposts {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let mut tracks = Vec::new();
+        let mut files = Vec::new();
+        for repost in self.user.reposts(&self.inner.sc_client)? {
+            match repost {
+                soundcloud::Repost::Track { track, .. } => tracks.push(*track),
+                soundcloud::Repost::Playlist { playlist, .. } => {
+                    files.push(playlist_entry(&self.inner, *playlist));
+                }
+                soundcloud::Repost::Other => (),
+            }
+        }
+        files.extend(track_files(
+            &self.inner,
+            &self.inner.filenames.reposts,
+            tracks,
+        ));
+        Ok(files)
     }
 }
 
-fn is_valid_file(name: impl AsRef<str>) -> bool {
+/// The directory of the user the client is logged in as, which holds the collections that are
+/// only accessible to that user. The user is only fetched when a collection is listed.
+#[derive(Clone)]
+pub struct MeDir {
+    inner: Arc<RootState>,
+}
+
+impl MeDir {
+    fn feed(&self, feed: Feed) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::MeFeed(MeFeed {
+            inner: self.inner.clone(),
+            feed,
+        }))
+    }
+}
+
+impl filesystem::Meta for MeDir {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        Some("me".to_string())
+    }
+}
+
+impl filesystem::Directory<Root> for MeDir {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        Ok(Feed::ALL
+            .iter()
+            .map(|feed| (feed.name().to_string(), self.feed(*feed)))
+            .collect())
+    }
+
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        Feed::ALL
+            .iter()
+            .find(|feed| feed.name() == name)
+            .map(|feed| self.feed(*feed))
+            .ok_or(Error::ChildNotFound)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Feed {
+    /// The tracks and playlists posted and reposted by the users that are followed.
+    Stream,
+    /// The liked tracks and playlists.
+    Likes,
+    /// The tracks that were played recently.
+    History,
+    /// The uploads that are not public.
+    Private,
+}
+
+impl Feed {
+    const ALL: [Feed; 4] = [Feed::Stream, Feed::Likes, Feed::History, Feed::Private];
+
+    fn name(self) -> &'static str {
+        match self {
+            Feed::Stream => "stream",
+            Feed::Likes => "likes",
+            Feed::History => "history",
+            Feed::Private => "private",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MeFeed {
+    inner: Arc<RootState>,
+    feed: Feed,
+}
+
+impl filesystem::Meta for MeFeed {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("me:{}", self.feed.name()))
+    }
+}
+
+impl filesystem::Directory<Root> for MeFeed {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let client = &self.inner.sc_client;
+        let filenames = &self.inner.filenames;
+        let (tracks, playlists) = match self.feed {
+            Feed::Stream => {
+                let mut tracks = Vec::new();
+                let mut playlists = Vec::new();
+                for activity in client.stream(FEED_LIMIT)? {
+                    match activity {
+                        soundcloud::Activity::Track { origin } => tracks.push(*origin),
+                        soundcloud::Activity::Playlist { origin } => playlists.push(*origin),
+                        soundcloud::Activity::Other => (),
+                    }
+                }
+                (tracks, playlists)
+            }
+            Feed::Likes => (client.me()?.favorites(client)?, client.liked_playlists()?),
+            Feed::History => (client.play_history(FEED_LIMIT)?, Vec::new()),
+            Feed::Private => (client.private_tracks()?, Vec::new()),
+        };
+        let template = match self.feed {
+            Feed::Private => &filenames.tracks,
+            _ => &filenames.favorites,
+        };
+
+        // The stream and history may list the same item more than once, only the most recent
+        // occurrence is kept.
+        let mut seen = HashSet::new();
+        let tracks: Vec<_> = tracks.into_iter().filter(|t| seen.insert(t.id)).collect();
+        let mut seen = HashSet::new();
+        let mut files: Vec<_> = playlists
+            .into_iter()
+            .filter(|p| seen.insert(p.id))
+            .map(|playlist| playlist_entry(&self.inner, playlist))
+            .collect();
+        files.extend(track_files(&self.inner, template, tracks));
+        Ok(files)
+    }
+}
+
+/// Performs a search for every name that is looked up in it. The directory itself is empty.
+#[derive(Clone)]
+pub struct SearchRoot {
+    inner: Arc<RootState>,
+}
+
+impl filesystem::Meta for SearchRoot {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        Some("search".to_string())
+    }
+}
+
+impl filesystem::Directory<Root> for SearchRoot {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        Ok(filesystem::Node::Directory(Dir::Search(SearchResults {
+            inner: self.inner.clone(),
+            query: name.to_string(),
+        })))
+    }
+}
+
+/// The tracks matching a query, with the matching users and playlists in subdirectories.
+#[derive(Clone)]
+pub struct SearchResults {
+    inner: Arc<RootState>,
+    query: String,
+}
+
+impl SearchResults {
+    fn users(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::SearchUsers(SearchUsers {
+            inner: self.inner.clone(),
+            query: self.query.clone(),
+        }))
+    }
+
+    fn playlists(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::SearchPlaylists(SearchPlaylists {
+            inner: self.inner.clone(),
+            query: self.query.clone(),
+        }))
+    }
+}
+
+impl filesystem::Meta for SearchResults {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("search:{}", self.query))
+    }
+}
+
+impl filesystem::Directory<Root> for SearchResults {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let tracks = self
+            .inner
+            .sc_client
+            .search_tracks(&self.query, self.inner.search_limit)?;
+        let mut files = vec![
+            ("users".to_string(), self.users()),
+            ("playlists".to_string(), self.playlists()),
+        ];
+        files.extend(track_files(
+            &self.inner,
+            &self.inner.filenames.favorites,
+            tracks,
+        ));
+        Ok(files)
+    }
+
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        match name {
+            "users" => Ok(self.users()),
+            "playlists" => Ok(self.playlists()),
+            _ => self
+                .files()?
+                .into_iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, node)| node)
+                .ok_or(Error::ChildNotFound),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchUsers {
+    inner: Arc<RootState>,
+    query: String,
+}
+
+impl filesystem::Meta for SearchUsers {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("search:{}:users", self.query))
+    }
+}
+
+impl filesystem::Directory<Root> for SearchUsers {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let users = self
+            .inner
+            .sc_client
+            .search_users(&self.query, self.inner.search_limit)?;
+        Ok(users
+            .into_iter()
+            .map(|user| {
+                (
+                    user.permalink.clone(),
+                    filesystem::Node::Symlink(Link::User(Box::new(UserReference {
+                        user,
+                        depth: 3,
+                    }))),
+                )
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchPlaylists {
+    inner: Arc<RootState>,
+    query: String,
+}
+
+impl filesystem::Meta for SearchPlaylists {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("search:{}:playlists", self.query))
+    }
+}
+
+impl filesystem::Directory<Root> for SearchPlaylists {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let playlists = self
+            .inner
+            .sc_client
+            .search_playlists(&self.query, self.inner.search_limit)?;
+        Ok(playlists
+            .into_iter()
+            .map(|playlist| playlist_entry(&self.inner, playlist))
+            .collect())
+    }
+}
+
+/// Looks up the names in it as URLs of the website. The name is either a percent-encoded URL, e.g.
+/// "https:%2F%2Fsoundcloud.com%2Fbryan%2Fsbahn-sounds", or the path of the URL spread over
+/// multiple directories, e.g. "bryan/sbahn-sounds" and "bryan/sets/field-recordings". Looking up
+/// an incomplete path yields a directory in which the rest of the path can be looked up. For a
+/// user, that directory also holds the profile of the user.
+#[derive(Clone)]
+pub struct ResolveDir {
+    inner: Arc<RootState>,
+    // The components of the path that have been looked up so far.
+    prefix: Vec<String>,
+    // The profile of the user the prefix refers to, if it is only the name of a user.
+    profile: Option<UserProfile>,
+}
+
+impl ResolveDir {
+    fn node(&self, resource: soundcloud::Resource) -> filesystem::Node<Root> {
+        match resource {
+            soundcloud::Resource::Track(track) => filesystem::Node::File(TrackAudio {
+                inner: self.inner.clone(),
+                track: *track,
+                playlist: None,
+            }),
+            soundcloud::Resource::Playlist(playlist) => {
+                filesystem::Node::Directory(Dir::Playlist(PlaylistTracks {
+                    inner: self.inner.clone(),
+                    playlist: Arc::new(*playlist),
+                }))
+            }
+            soundcloud::Resource::User(user) => {
+                let options = self.inner.user_options(&user.permalink);
+                filesystem::Node::Directory(Dir::Resolve(Box::new(ResolveDir {
+                    inner: self.inner.clone(),
+                    prefix: vec![user.permalink.clone()],
+                    profile: Some(UserProfile {
+                        inner: self.inner.clone(),
+                        user: *user,
+                        options,
+                    }),
+                })))
+            }
+        }
+    }
+}
+
+impl filesystem::Meta for ResolveDir {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        if let Some(profile) = &self.profile {
+            return profile.metadata();
+        }
+        Ok(self.inner.dir_metadata())
+    }
+
+    fn id(&self) -> Option<String> {
+        match self.prefix.len() {
+            0 => Some("resolve".to_string()),
+            _ => Some(format!("resolve:{}", self.prefix.join(":"))),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        match &self.profile {
+            Some(profile) => profile.xattrs(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl filesystem::Directory<Root> for ResolveDir {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        match &self.profile {
+            Some(profile) => profile.files(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        let decoded = percent_decode_str(name).decode_utf8_lossy();
+        if self.prefix.is_empty() && decoded.contains("://") {
+            let resource = self.inner.sc_client.resolve(&decoded)?;
+            return Ok(self.node(resource));
+        }
+
+        // The entries of the profile take precedence over the paths of tracks, which do not have
+        // an extension.
+        if let Some(profile) = self.profile.as_ref().filter(|_| name != "sets") {
+            match profile.file_by_name(name) {
+                Err(Error::ChildNotFound) => (),
+                rs => return rs,
+            }
+        }
+
+        let mut path = self.prefix.clone();
+        path.push(name.to_string());
+        let complete = match path.len() {
+            1 => true,
+            2 => path[1] != "sets",
+            3 => path[1] == "sets",
+            _ => return Err(Error::ChildNotFound),
+        };
+        if !complete {
+            return Ok(filesystem::Node::Directory(Dir::Resolve(Box::new(
+                ResolveDir {
+                    inner: self.inner.clone(),
+                    prefix: path,
+                    profile: None,
+                },
+            ))));
+        }
+        let resource = self.inner.sc_client.resolve(&path.join("/"))?;
+        Ok(self.node(resource))
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaylistTracks {
+    inner: Arc<RootState>,
+    playlist: Arc<soundcloud::Playlist>,
+}
+
+impl filesystem::Meta for PlaylistTracks {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.playlist.last_modified,
+            ctime: self.playlist.created_at,
+            perm: 0o555,
+        })
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("playlist:{}", self.playlist.id))
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        let playlist = &self.playlist;
+        Ok(xattrs(vec![
+            ("id", Some(playlist.id.to_string())),
+            ("permalink_url", Some(playlist.permalink_url.clone())),
+            ("title", Some(playlist.title.clone())),
+            ("genre", playlist.genre.clone()),
+            ("license", Some(playlist.license.clone())),
+            ("tag_list", Some(playlist.tag_list.clone())),
+            ("track_count", Some(playlist.track_count.to_string())),
+        ]))
+    }
+}
+
+impl filesystem::Directory<Root> for PlaylistTracks {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        // The default template prefixes the names with the position in the playlist so the
+        // tracks sort in playlist order, even in programs that do not preserve the directory
+        // order.
+        let names = self
+            .inner
+            .filenames
+            .playlists
+            .render_all(self.playlist.tracks.iter(), true);
+        let files: Vec<_> = names
+            .into_iter()
+            .zip(&self.playlist.tracks)
+            .enumerate()
+            .map(|(i, (name, track))| {
+                (
+                    name,
+                    filesystem::Node::File(TrackAudio {
+                        inner: self.inner.clone(),
+                        track: track.clone(),
+                        playlist: Some((self.playlist.clone(), i)),
+                    }),
+                )
+            })
+            .collect();
+        Ok(files)
+    }
+}
+
+#[derive(Clone)]
+pub struct UserProfile {
+    inner: Arc<RootState>,
+    user: soundcloud::User,
+    // Child directories are only added if the depth is at least 1, to prevent recursing too
+    // deeply.
+    options: UserOptions,
+}
+
+impl UserProfile {
+    fn favorites(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::UserFavorites(Box::new(UserFavorites {
+            user: self.user.clone(),
+            inner: self.inner.clone(),
+        })))
+    }
+
+    fn following(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::UserFollowing(Box::new(UserFollowing {
+            inner: self.inner.clone(),
+            user: self.user.clone(),
+        })))
+    }
+
+    fn followers(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::UserFollowers(Box::new(UserFollowers {
+            inner: self.inner.clone(),
+            user: self.user.clone(),
+        })))
+    }
+
+    fn playlists(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::UserPlaylists(Box::new(UserPlaylists {
+            inner: self.inner.clone(),
+            user: self.user.clone(),
+        })))
+    }
+
+    fn reposts(&self) -> filesystem::Node<Root> {
+        filesystem::Node::Directory(Dir::UserReposts(Box::new(UserReposts {
+            inner: self.inner.clone(),
+            user: self.user.clone(),
+        })))
+    }
+
+    fn tracks(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Error> {
+        let tracks = self.user.tracks(&self.inner.sc_client)?;
+        Ok(track_files(
+            &self.inner,
+            &self.inner.filenames.tracks,
+            tracks,
+        ))
+    }
+}
+
+impl filesystem::Meta for UserProfile {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.user.last_modified,
+            ctime: self.user.last_modified,
+            perm: 0o555,
+        })
+    }
+
+    fn id(&self) -> Option<String> {
+        Some(format!("user:{}", self.user.id))
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        let user = &self.user;
+        Ok(xattrs(vec![
+            ("id", Some(user.id.to_string())),
+            ("permalink_url", Some(user.permalink_url.clone())),
+            ("username", Some(user.username.clone())),
+            ("full_name", Some(user.full_name.clone())),
+            ("city", user.city.clone()),
+            ("country", user.country.clone()),
+            ("website", user.website.clone()),
+            ("track_count", Some(user.track_count.to_string())),
+            ("playlist_count", Some(user.playlist_count.to_string())),
+            ("followers_count", Some(user.followers_count.to_string())),
+            ("followings_count", Some(user.followings_count.to_string())),
+            (
+                "public_favorites_count",
+                Some(user.public_favorites_count.to_string()),
+            ),
+        ]))
+    }
+}
+
+impl filesystem::Directory<Root> for UserProfile {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root>)>, Self::Error> {
+        let mut files = Vec::new();
+        if self.options.depth > 0 {
+            if self.options.favorites {
+                files.push(("favorites".to_string(), self.favorites()));
+            }
+            if self.options.following {
+                files.push(("following".to_string(), self.following()));
+            }
+            if self.options.followers {
+                files.push(("followers".to_string(), self.followers()));
+            }
+            if self.options.playlists {
+                files.push(("playlists".to_string(), self.playlists()));
+            }
+            if self.options.reposts {
+                files.push(("reposts".to_string(), self.reposts()));
+            }
+        }
+        if self.options.tracks {
+            files.extend(self.tracks()?);
+        }
+        Ok(files)
+    }
+
+    fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root>, Self::Error> {
+        let options = &self.options;
+        match name {
+            "favorites" if options.depth > 0 && options.favorites => return Ok(self.favorites()),
+            "following" if options.depth > 0 && options.following => return Ok(self.following()),
+            "followers" if options.depth > 0 && options.followers => return Ok(self.followers()),
+            "playlists" if options.depth > 0 && options.playlists => return Ok(self.playlists()),
+            "reposts" if options.depth > 0 && options.reposts => return Ok(self.reposts()),
+            _ if !options.tracks => return Err(Error::ChildNotFound),
+            _ => (),
+        }
+
+        // Look up the track directly if the name identifies it, so the tracks of the user do not
+        // have to be listed.
+        let template = &self.inner.filenames.tracks;
+        if !template.is_resolvable() || template.is_shortened(name) {
+            return self
+                .tracks()?
+                .into_iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, node)| node)
+                .ok_or(Error::ChildNotFound);
+        }
+        let key = template.parse_name(name).ok_or(Error::ChildNotFound)?;
+        let track = track_by_key(&self.inner.sc_client, &key, Some(&self.user.permalink))?;
+        if track.user.id != self.user.id || !template.matches(name, &track) {
+            return Err(Error::ChildNotFound);
+        }
+        Ok(filesystem::Node::File(TrackAudio {
+            inner: self.inner.clone(),
+            track,
+            playlist: None,
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub struct TrackAudio {
+    inner: Arc<RootState>,
+    track: soundcloud::Track,
+    // The playlist this track is served from and its index in it, used for album metadata.
+    playlist: Option<(Arc<soundcloud::Playlist>, usize)>,
+}
+
+impl TrackAudio {
+    fn id3_tag(&self) -> Result<impl io::Read + io::Seek, soundcloud::Error> {
+        tag_for_track(
+            &self.track,
+            self.playlist.as_ref().map(|(p, i)| (p.as_ref(), *i)),
+            self.inner.id3_download_images,
+            self.inner.id3_parse_strings,
+        )
+    }
+}
+
+impl filesystem::Meta for TrackAudio {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.track.last_modified,
+            ctime: self.track.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn id(&self) -> Option<String> {
+        // Tracks in playlists are tagged with the album info, so they differ from the track
+        // elsewhere.
+        Some(match &self.playlist {
+            Some((playlist, i)) => format!("playlist:{}:{}", playlist.id, i),
+            None => format!("track:{}", self.track.id),
+        })
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, String)>, Self::Error> {
+        let track = &self.track;
+        Ok(xattrs(vec![
+            ("id", Some(track.id.to_string())),
+            ("permalink_url", Some(track.permalink_url.clone())),
+            ("title", Some(track.title.clone())),
+            ("user", Some(track.user.permalink.clone())),
+            ("genre", track.genre.clone()),
+            ("bpm", track.bpm.map(|bpm| bpm.to_string())),
+            ("license", Some(track.license.clone())),
+            ("tag_list", Some(track.tag_list.clone())),
+            ("downloadable", Some(track.downloadable.to_string())),
+        ]))
+    }
+}
+
+impl filesystem::File for TrackAudio {
+    type Reader = Concat<Box<dyn ReadSeek + Send>>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        let id3_tag = self.id3_tag()?;
+
+        let remote_mp3_size = self.track.audio_size() as u64;
+        let padding_len = mp3::ZERO_FRAME.len() as u64;
+        let mp3_total_size =
+            remote_mp3_size + PADDING_START * padding_len + PADDING_END * padding_len;
+        let mp3_header = mp3::cbr_header(mp3_total_size);
+        let first_frame_size = mp3_header.len() as u64;
+
+        // Hackety hack: the file concatenation abstraction is able to lazily index the
+        // size of the underlying files. This ensures for programs that just want to probe
+        // the audio file's metadata, no request for the actual audio file will be
+        // performed.
+        // However, because reading programs may read beyond the metadata, the audio may
+        // still be accessed. To counter this, we jam a very large swath of zero bytes in
+        // between the metadata and audio stream to saturate the read buffer without the
+        // audio stream.
+        let padding_start = mp3::zero_frames(PADDING_START);
+        // We also need some padding at the end for players that try to
+        // read ID3v1 metadata.
+        let padding_end = mp3::zero_frames(PADDING_END);
+
+        let track_cp = self.track.clone();
+        let inner_cp = self.inner.clone();
+        let audio = LazyOpen::with_size_hint(remote_mp3_size, move || {
+            let f = track_cp
+                .audio(&inner_cp.sc_client)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{}", err)))?;
+            Ok(Skip::new(f, first_frame_size))
+        });
+        // Serve the parts of the audio that have been downloaded before from the local cache.
+        let audio = match &self.inner.audio_cache {
+            Some(cache) => {
+                Box::<dyn ReadSeek + Send>::from(Box::new(cache.open(self.track.id as u64, audio)?))
+            }
+            None => Box::<dyn ReadSeek + Send>::from(Box::new(audio)),
+        };
+
+        let concat = if self.inner.mpeg_padding {
+            Concat::new(vec![
+                Box::<dyn ReadSeek + Send>::from(Box::new(id3_tag)),
+                Box::<dyn ReadSeek + Send>::from(Box::new(io::Cursor::new(mp3_header))),
+                Box::<dyn ReadSeek + Send>::from(Box::new(padding_start)),
+                audio,
+                Box::<dyn ReadSeek + Send>::from(Box::new(padding_end)),
+            ])
+        } else {
+            Concat::new(vec![
+                Box::<dyn ReadSeek + Send>::from(Box::new(id3_tag)),
+                audio,
+            ])
+        };
+        Ok(concat)
+    }
+
+    fn size(&self) -> Result<u64, Self::Error> {
+        let id3_tag_size = {
+            let mut b = self.id3_tag()?;
+            b.seek(io::SeekFrom::End(0)).unwrap()
+        };
+        let padding_size = if self.inner.mpeg_padding {
+            let padding_len = mp3::ZERO_FRAME.len() as u64;
+            PADDING_START * padding_len + PADDING_END * padding_len
+        } else {
+            0
+        };
+        Ok(id3_tag_size + padding_size + self.track.audio_size() as u64)
+    }
+}
+
+#[derive(Clone)]
+pub enum Link {
+    User(Box<UserReference>),
+    Track(TrackReference),
+}
+
+impl filesystem::Meta for Link {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        match self {
+            Link::User(l) => l.metadata(),
+            Link::Track(l) => l.metadata(),
+        }
+    }
+
+    fn id(&self) -> Option<String> {
+        match self {
+            Link::User(l) => l.id(),
+            Link::Track(l) => l.id(),
+        }
+    }
+}
+
+impl filesystem::Symlink for Link {
+    fn read_link(&self) -> Result<PathBuf, Self::Error> {
+        match self {
+            Link::User(l) => l.read_link(),
+            Link::Track(l) => l.read_link(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UserReference {
+    user: soundcloud::User,
+    // The number of directories between the root and the directory of the link.
+    depth: usize,
+}
+
+impl filesystem::Meta for UserReference {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.user.last_modified,
+            ctime: self.user.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn id(&self) -> Option<String> {
+        // Links at different depths have different targets, so they are different nodes.
+        Some(format!("user:{}:link:{}", self.user.id, self.depth))
+    }
+}
+
+impl filesystem::Symlink for UserReference {
+    fn read_link(&self) -> Result<PathBuf, Self::Error> {
+        let mut path: PathBuf = (0..self.depth).map(|_| "..").collect();
+        path.push(&self.user.permalink);
+        Ok(path)
+    }
+}
+
+/// A symlink that was created to like a track. It is not listed anywhere, so it is only reported
+/// to the kernel right after its creation.
+#[derive(Clone)]
+pub struct TrackReference {
+    target: PathBuf,
+    // The modification time of the track.
+    mtime: DateTime<Utc>,
+}
+
+impl filesystem::Meta for TrackReference {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.mtime,
+            ctime: self.mtime,
+            perm: 0o444,
+        })
+    }
+}
+
+impl filesystem::Symlink for TrackReference {
+    fn read_link(&self) -> Result<PathBuf, Self::Error> {
+        Ok(self.target.clone())
+    }
+}
+
+/// Checks that the user is the one the client is logged in as, which is the only user whose
+/// collections can be changed.
+fn check_own(inner: &RootState, user: &soundcloud::User) -> Result<(), Error> {
+    if inner.me_id()? != user.id {
+        return Err(Error::NotPermitted);
+    }
+    Ok(())
+}
+
+/// Names a list of tracks using a template.
+fn track_files(
+    inner: &Arc<RootState>,
+    template: &Template,
+    tracks: Vec<soundcloud::Track>,
+) -> Vec<(String, filesystem::Node<Root>)> {
+    let names = template.render_all(tracks.iter(), false);
+    names
+        .into_iter()
+        .zip(tracks)
+        .map(|(name, track)| {
+            let node = filesystem::Node::File(TrackAudio {
+                inner: inner.clone(),
+                track,
+                playlist: None,
+            });
+            (name, node)
+        })
+        .collect()
+}
+
+/// Creates the directory of a playlist that is listed among the playlists of other users.
+fn playlist_entry(
+    inner: &Arc<RootState>,
+    playlist: soundcloud::Playlist,
+) -> (String, filesystem::Node<Root>) {
+    // Playlists of different users may have the same permalink.
+    let name = format!("{}_-_{}", playlist.user.permalink, playlist.permalink);
+    let node = filesystem::Node::Directory(Dir::Playlist(PlaylistTracks {
+        inner: inner.clone(),
+        playlist: Arc::new(playlist),
+    }));
+    (name, node)
+}
+
+/// Fetches the track identified by the fields parsed from a filename. If the name does not
+/// include the uploader, the track is looked up among the tracks of `user`.
+fn track_by_key(
+    client: &soundcloud::Client,
+    key: &Key,
+    user: Option<&str>,
+) -> Result<soundcloud::Track, Error> {
+    if let Some(id) = key.id {
+        return Ok(soundcloud::Track::by_id(client, id)?);
+    }
+    match (key.user.as_deref().or(user), &key.permalink) {
+        (Some(user_pl), Some(track_pl)) => {
+            Ok(soundcloud::Track::by_permalink(client, user_pl, track_pl)?)
+        }
+        _ => Err(Error::ChildNotFound),
+    }
+}
+
+/// Prefixes the names of the attributes and leaves out those without a value.
+fn xattrs(attrs: Vec<(&str, Option<String>)>) -> Vec<(String, String)> {
+    attrs
+        .into_iter()
+        .filter_map(|(name, value)| Some((format!("{}{}", XATTR_PREFIX, name), value?)))
+        .collect()
+}
+
+fn is_valid_file(name: impl AsRef<str>) -> bool {
     match name.as_ref() {
         "AACS" | "BACKUP" | "PLAYLIST" | "BDMV" | "bdmv" => false,
         name if name.starts_with('.') => false,
         _ => true,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::harness::Harness;
+    use crate::filesystem::{
+        CacheConfig, CacheRoot, Directory, File, Meta, Node, NodeType, Symlink,
+    };
+    use crate::soundcloud::mock::MockServer;
+    use std::io::Read;
+
+    fn test_root(server: &MockServer) -> Root {
+        root_with_client(server.client())
+    }
+
+    fn root_with_client(sc_client: soundcloud::Client) -> Root {
+        root_with_users(sc_client, vec![UserOptions::new("alice")])
+    }
+
+    fn root_with_users(sc_client: soundcloud::Client, users: Vec<UserOptions>) -> Root {
+        root_with_filenames(sc_client, users, Filenames::default())
+    }
+
+    fn root_with_filenames(
+        sc_client: soundcloud::Client,
+        users: Vec<UserOptions>,
+        filenames: Filenames,
+    ) -> Root {
+        Root::new(Arc::new(test_state(sc_client, users, filenames)))
+    }
+
+    fn test_state(
+        sc_client: soundcloud::Client,
+        users: Vec<UserOptions>,
+        filenames: Filenames,
+    ) -> RootState {
+        RootState {
+            sc_client,
+            users,
+            filenames,
+            search_limit: 10,
+            mpeg_padding: false,
+            id3_download_images: false,
+            id3_parse_strings: false,
+            audio_cache: None,
+            derived_depths: Mutex::default(),
+            derived_depths_ttl: Duration::from_secs(60 * 60),
+            me_id: Mutex::default(),
+            mounted_at: Utc::now(),
+        }
+    }
+
+    fn names(dir: &Dir) -> Vec<String> {
+        dir.files()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    fn dir(node: Node<Root>) -> Dir {
+        match node {
+            Node::Directory(d) => d,
+            _ => panic!("not a directory"),
+        }
+    }
+
+    #[test]
+    fn user_list() {
+        let server = MockServer::start();
+        let root = test_root(&server).root();
+        assert_eq!(vec!["search", "resolve", "alice"], names(&root));
+
+        // Directories that do not exist on SoundCloud report the time of the mount.
+        let mtime = root.metadata().unwrap().mtime;
+        for name in &["search", "resolve"] {
+            let dir = dir(root.file_by_name(name).unwrap());
+            assert_eq!(mtime, dir.metadata().unwrap().mtime);
+        }
+    }
+
+    #[test]
+    fn user_profile() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        assert_eq!(
+            vec![
+                "favorites",
+                "following",
+                "playlists",
+                "reposts",
+                "first-song.mp3",
+                "second-song.mp3",
+                "third-song.mp3",
+            ],
+            names(&alice)
+        );
+    }
+
+    #[test]
+    fn user_profile_not_shown() {
+        let server = MockServer::start();
+        let bob = dir(test_root(&server).root().file_by_name("bob").unwrap());
+        let names = names(&bob);
+        assert_eq!(250, names.len());
+        assert!(names.iter().all(|n| n.ends_with(".mp3")));
+    }
+
+    #[test]
+    fn user_options() {
+        let server = MockServer::start();
+        let mut options = UserOptions::new("alice");
+        options.favorites = false;
+        options.tracks = false;
+        let root = root_with_users(server.client(), vec![options]);
+        let alice = dir(root.root().file_by_name("alice").unwrap());
+        assert_eq!(vec!["following", "playlists", "reposts"], names(&alice));
+        assert!(alice.file_by_name("favorites").is_err());
+        assert!(alice.file_by_name("first-song.mp3").is_err());
+    }
+
+    #[test]
+    fn user_depth() {
+        let server = MockServer::start();
+        let mut options = UserOptions::new("alice");
+        options.depth = 2;
+        let root = root_with_users(server.client(), vec![options]);
+        let bob = dir(root.root().file_by_name("bob").unwrap());
+        assert!(names(&bob).contains(&"favorites".to_string()));
+        let carol = dir(root.root().file_by_name("carol").unwrap());
+        assert!(!names(&carol).contains(&"favorites".to_string()));
+        // The followings are fetched once for all lookups.
+        let followings = || {
+            server
+                .requests()
+                .into_iter()
+                .filter(|r| r.starts_with("/users/1/followings?"))
+                .count()
+        };
+        assert_eq!(1, followings());
+
+        // The depths are computed again once they have expired.
+        let mut options = UserOptions::new("alice");
+        options.depth = 2;
+        let root = Root::new(Arc::new(RootState {
+            derived_depths_ttl: Duration::from_secs(0),
+            ..test_state(server.client(), vec![options], Filenames::default())
+        }));
+        root.root().file_by_name("bob").unwrap();
+        root.root().file_by_name("carol").unwrap();
+        assert_eq!(3, followings());
+
+        let mut options = UserOptions::new("alice");
+        options.depth = 0;
+        let root = root_with_users(server.client(), vec![options]);
+        let alice = dir(root.root().file_by_name("alice").unwrap());
+        assert!(names(&alice).iter().all(|n| n.ends_with(".mp3")));
+    }
+
+    #[test]
+    fn filename_templates() {
+        let server = MockServer::start();
+        let filenames = Filenames {
+            tracks: Template::parse("{title} [{id}].mp3").unwrap(),
+            favorites: Template::parse("{artist} - {title}.mp3").unwrap(),
+            reposts: Template::parse("{title}.mp3").unwrap(),
+            playlists: Template::parse("{index}. {title}.mp3").unwrap(),
+        };
+        let root = root_with_filenames(server.client(), vec![UserOptions::new("alice")], filenames);
+        let alice = dir(root.root().file_by_name("alice").unwrap());
+        assert!(names(&alice).contains(&"first song [101].mp3".to_string()));
+        assert!(alice.file_by_name("first song [101].mp3").is_ok());
+        assert!(alice.file_by_name("second song [101].mp3").is_err());
+        // Tracks of other users can not be reached through alice.
+        assert!(alice.file_by_name("track 0 [201].mp3").is_err());
+
+        let favorites = dir(alice.file_by_name("favorites").unwrap());
+        assert_eq!(
+            vec!["bob - track 0.mp3", "bob - track 1.mp3"],
+            names(&favorites)
+        );
+        assert!(favorites.file_by_name("bob - track 1.mp3").is_ok());
+
+        let playlists = dir(alice.file_by_name("playlists").unwrap());
+        let mixtape = dir(playlists.file_by_name("mixtape").unwrap());
+        assert_eq!(
+            vec!["01. first song.mp3", "02. track 0.mp3"],
+            names(&mixtape)
+        );
+    }
+
+    #[test]
+    fn reposts() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        let reposts = dir(alice.file_by_name("reposts").unwrap());
+        assert_eq!(
+            vec![
+                "bob_-_b-sides",
+                "bob_-_track-4.mp3",
+                "alice_-_second-song.mp3"
+            ],
+            names(&reposts)
+        );
+        let playlist = dir(reposts.file_by_name("bob_-_b-sides").unwrap());
+        assert_eq!(
+            vec!["01_-_bob_-_track-2.mp3", "02_-_bob_-_track-3.mp3"],
+            names(&playlist)
+        );
+    }
+
+    #[test]
+    fn me() {
+        let server = MockServer::start();
+        let root = root_with_client(server.login("alice")).root();
+        assert_eq!(vec!["me", "search", "resolve", "alice"], names(&root));
+        let me = dir(root.file_by_name("me").unwrap());
+        assert_eq!(vec!["stream", "likes", "history", "private"], names(&me));
+
+        let feed = |name| names(&dir(me.file_by_name(name).unwrap()));
+        assert_eq!(
+            vec!["bob_-_b-sides", "bob_-_track-9.mp3", "bob_-_track-0.mp3"],
+            feed("stream")
+        );
+        assert_eq!(
+            vec!["bob_-_b-sides", "bob_-_track-0.mp3", "bob_-_track-1.mp3"],
+            feed("likes")
+        );
+        assert_eq!(
+            vec!["bob_-_track-1.mp3", "alice_-_first-song.mp3"],
+            feed("history")
+        );
+        assert_eq!(vec!["secret-song.mp3"], feed("private"));
+
+        let root = test_root(&server).root();
+        assert!(root.file_by_name("me").is_err());
+
+        // The user is only fetched when needed, so a failure does not affect the root.
+        let root = root_with_client(server.login("nobody")).root();
+        assert_eq!(vec!["me", "search", "resolve", "alice"], names(&root));
+        let me = dir(root.file_by_name("me").unwrap());
+        assert!(dir(me.file_by_name("likes").unwrap()).files().is_err());
+    }
+
+    #[test]
+    fn followers() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        assert!(alice.file_by_name("followers").is_err());
+
+        let mut options = UserOptions::new("bob");
+        options.followers = true;
+        let root = root_with_users(server.client(), vec![options]);
+        let bob = dir(root.root().file_by_name("bob").unwrap());
+        let followers = dir(bob.file_by_name("followers").unwrap());
+        let files = followers.files().unwrap();
+        assert_eq!(1, files.len());
+        assert_eq!("alice", files[0].0);
+        match &files[0].1 {
+            Node::Symlink(link) => {
+                assert_eq!(PathBuf::from("../../alice"), link.read_link().unwrap());
+            }
+            _ => panic!("not a symlink"),
+        }
+    }
+
+    #[test]
+    fn search() {
+        let server = MockServer::start();
+        let search = dir(test_root(&server).root().file_by_name("search").unwrap());
+        assert!(names(&search).is_empty());
+
+        let results = dir(search.file_by_name("Song").unwrap());
+        assert_eq!(
+            vec![
+                "users",
+                "playlists",
+                "alice_-_first-song.mp3",
+                "alice_-_second-song.mp3",
+                "alice_-_third-song.mp3",
+            ],
+            names(&results)
+        );
+        assert!(results.file_by_name("alice_-_first-song.mp3").is_ok());
+        assert!(results.file_by_name("alice_-_secret-song.mp3").is_err());
+
+        let results = dir(search.file_by_name("track").unwrap());
+        assert_eq!(2 + 10, names(&results).len());
+
+        let results = dir(search.file_by_name("b").unwrap());
+        let users = dir(results.file_by_name("users").unwrap()).files().unwrap();
+        assert_eq!("bob", users[0].0);
+        match &users[0].1 {
+            Node::Symlink(link) => {
+                assert_eq!(PathBuf::from("../../../bob"), link.read_link().unwrap());
+            }
+            _ => panic!("not a symlink"),
+        }
+        let playlists = dir(results.file_by_name("playlists").unwrap());
+        assert_eq!(vec!["bob_-_b-sides"], names(&playlists));
+    }
+
+    #[test]
+    fn resolve() {
+        let server = MockServer::start();
+        let resolve = dir(test_root(&server).root().file_by_name("resolve").unwrap());
+        let track_id = |node: Node<Root>| match node {
+            Node::File(audio) => audio.track.id,
+            _ => panic!("not a file"),
+        };
+
+        let url = server.url("/alice/second-song").replace('/', "%2F");
+        assert_eq!(102, track_id(resolve.file_by_name(&url).unwrap()));
+        // The name of a user yields the same directory as the URL of the user.
+        let alice = dir(resolve.file_by_name("alice").unwrap());
+        let url = server.url("/alice").replace('/', "%2F");
+        let alice_url = dir(resolve.file_by_name(&url).unwrap());
+        assert_eq!(names(&alice_url), names(&alice));
+        assert!(names(&alice).contains(&"favorites".to_string()));
+        assert_eq!(101, track_id(alice.file_by_name("first-song.mp3").unwrap()));
+        assert_eq!(101, track_id(alice.file_by_name("first-song").unwrap()));
+        assert!(alice.file_by_name("nope").is_err());
+        assert!(resolve.file_by_name("nobody").is_err());
+
+        let sets = dir(alice.file_by_name("sets").unwrap());
+        let mixtape = dir(sets.file_by_name("mixtape").unwrap());
+        assert_eq!(2, names(&mixtape).len());
+
+        let url = server.url("/bob").replace('/', "%2F");
+        let bob = dir(resolve.file_by_name(&url).unwrap());
+        assert!(names(&bob).iter().all(|n| n.ends_with(".mp3")));
+    }
+
+    #[test]
+    fn favorites() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        let favorites = dir(alice.file_by_name("favorites").unwrap());
+        assert_eq!(
+            vec!["bob_-_track-0.mp3", "bob_-_track-1.mp3"],
+            names(&favorites)
+        );
+    }
+
+    #[test]
+    fn following() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        let following = dir(alice.file_by_name("following").unwrap());
+        let files = following.files().unwrap();
+        assert_eq!(1, files.len());
+        assert_eq!("bob", files[0].0);
+        match &files[0].1 {
+            Node::Symlink(link) => {
+                assert_eq!(PathBuf::from("../../bob"), link.read_link().unwrap());
+            }
+            _ => panic!("not a symlink"),
+        }
+    }
+
+    #[test]
+    fn playlists() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        let playlists = dir(alice.file_by_name("playlists").unwrap());
+        assert_eq!(vec!["mixtape"], names(&playlists));
+        let mixtape = dir(playlists.file_by_name("mixtape").unwrap());
+        assert_eq!(
+            vec!["01_-_alice_-_first-song.mp3", "02_-_bob_-_track-0.mp3"],
+            names(&mixtape)
+        );
+    }
+
+    #[test]
+    fn track_audio() {
+        let server = MockServer::start();
+        let alice = dir(test_root(&server).root().file_by_name("alice").unwrap());
+        let track = match alice.file_by_name("first-song.mp3").unwrap() {
+            Node::File(f) => f,
+            _ => panic!("not a file"),
+        };
+
+        let mut buf = Vec::new();
+        track.open_ro().unwrap().read_to_end(&mut buf).unwrap();
+        // The size is an estimate based on the duration, the first frame of the audio is skipped.
+        assert!(buf.len() as u64 <= track.size().unwrap());
+        assert_eq!(b"ID3", &buf[..3]);
+        let audio = MockServer::audio(101);
+        assert!(buf.ends_with(&audio[audio.len() - 1000..]));
+    }
+
+    #[test]
+    fn fs_lookup() {
+        let server = MockServer::start();
+        let fs = Harness::new(&test_root(&server));
+
+        let attrs = fs.getattr("alice/first-song.mp3").unwrap();
+        assert_eq!(fuse::FileType::RegularFile, attrs.kind);
+        assert_eq!(0o444, attrs.perm);
+        assert_eq!(
+            fuse::FileType::Directory,
+            fs.getattr("alice/playlists").unwrap().kind
+        );
+        // Inodes are stable across lookups.
+        assert_eq!(attrs.ino, fs.lookup("alice/first-song.mp3").unwrap().ino);
+
+        assert_eq!(Err(libc::ENOENT), fs.lookup("alice/nope.mp3").map(|_| ()));
+        assert_eq!(Err(libc::ENOENT), fs.lookup("alice/.hidden").map(|_| ()));
+        assert_eq!(
+            Err(libc::ENOTDIR),
+            fs.lookup("alice/first-song.mp3/x").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn fs_stable_inodes() {
+        let server = MockServer::start();
+        let fs = Harness::new(&test_root(&server));
+        let ino = fs.lookup("alice/first-song.mp3").unwrap().ino;
+        let remounted = Harness::new(&test_root(&server));
+        assert_eq!(ino, remounted.lookup("alice/first-song.mp3").unwrap().ino);
+
+        // The same track is the same inode, regardless of where it is found.
+        let favorites = fs.list("alice/favorites").unwrap();
+        assert_eq!(fs.lookup("bob/track-0.mp3").unwrap().ino, favorites[0].ino);
+        // Except in playlists, because those have different tags.
+        let mixtape = fs.list("alice/playlists/mixtape").unwrap();
+        assert_ne!(ino, mixtape[0].ino);
+    }
+
+    #[test]
+    fn fs_xattrs() {
+        let server = MockServer::start();
+        let fs = Harness::new(&test_root(&server));
+
+        let names = fs.listxattr("alice/first-song.mp3").unwrap();
+        assert!(names.contains(&"user.soundcloud.genre".to_string()));
+        // Tracks without bpm do not have the attribute.
+        assert!(!names.contains(&"user.soundcloud.bpm".to_string()));
+        assert_eq!(
+            b"101".to_vec(),
+            fs.getxattr("alice/first-song.mp3", "user.soundcloud.id")
+                .unwrap()
+        );
+        assert_eq!(
+            b"false".to_vec(),
+            fs.getxattr("alice/first-song.mp3", "user.soundcloud.downloadable")
+                .unwrap()
+        );
+        assert_eq!(
+            b"3".to_vec(),
+            fs.getxattr("alice", "user.soundcloud.track_count").unwrap()
+        );
+        assert_eq!(
+            Err(libc::ENODATA),
+            fs.getxattr("alice", "user.soundcloud.bpm")
+        );
+    }
+
+    #[test]
+    fn fs_list() {
+        let server = MockServer::start();
+        let fs = Harness::new(&test_root(&server));
+
+        let all = fs.list("bob").unwrap();
+        assert_eq!(250, all.len());
+        // Listing in small batches should result in the same entries.
+        assert_eq!(all, fs.list_batched("bob", 7).unwrap());
+        // The inodes in listings match those from lookups.
+        assert_eq!(fs.lookup("bob/track-3.mp3").unwrap().ino, all[3].ino);
+
+        let following = fs.list("alice/following").unwrap();
+        assert_eq!(fuse::FileType::Symlink, following[0].kind);
+        assert_eq!(
+            PathBuf::from("../../bob"),
+            fs.readlink("alice/following/bob").unwrap()
+        );
+    }
+
+    #[test]
+    fn fs_read() {
+        let server = MockServer::start();
+        let fs = Harness::new(&test_root(&server));
+
+        let all = fs.read_all("alice/first-song.mp3").unwrap();
+        assert_eq!(
+            &all[100..200],
+            &fs.read("alice/first-song.mp3", 100, 100).unwrap()[..]
+        );
+        let size = fs.getattr("alice/first-song.mp3").unwrap().size;
+        assert!(fs
+            .read("alice/first-song.mp3", size, 100)
+            .unwrap()
+            .is_empty());
+        assert_eq!(Err(libc::EISDIR), fs.read("alice", 0, 100));
+    }
+
+    #[test]
+    fn fs_cached() {
+        let server = MockServer::start();
+        let fs = Harness::new(&CacheRoot::new(&test_root(&server), CacheConfig::default()));
+
+        let list = fs.list("alice").unwrap();
+        let n = server.requests().len();
+        assert_eq!(list, fs.list("alice").unwrap());
+        assert_eq!(n, server.requests().len());
+        assert_eq!(Err(libc::ENOENT), fs.lookup("alice/nope.mp3").map(|_| ()));
+    }
+
+    #[test]
+    fn fs_like() {
+        let server = MockServer::start();
+        let root = root_with_client(server.login("alice"));
+        let fs = Harness::new(&CacheRoot::new(&root, CacheConfig::default()));
+        let favorites = |fs: &Harness<_>| -> Vec<String> {
+            let list = fs.list("alice/favorites").unwrap();
+            list.into_iter().map(|e| e.name).collect()
+        };
+        assert_eq!(2, favorites(&fs).len());
+
+        let attrs = fs
+            .symlink("../../bob/track-5.mp3", "alice/favorites/bob_-_track-5.mp3")
+            .unwrap();
+        assert_eq!(fuse::FileType::Symlink, attrs.kind);
+        assert_eq!(attrs.kind, fs.getattr_ino(attrs.ino).unwrap().kind);
+        assert!(favorites(&fs).contains(&"bob_-_track-5.mp3".to_string()));
+        assert_eq!(
+            Err(libc::EPERM),
+            fs.symlink("../../bob/track-6.mp3", "alice/favorites/x")
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(libc::EPERM),
+            fs.link("alice/first-song.mp3", "alice/favorites/x")
+                .map(|_| ())
+        );
+
+        let ino = fs.lookup("alice/first-song.mp3").unwrap().ino;
+        let attrs = fs
+            .link(
+                "alice/first-song.mp3",
+                "alice/favorites/alice_-_first-song.mp3",
+            )
+            .unwrap();
+        assert_eq!(ino, attrs.ino);
+
+        fs.create("alice/favorites/bob_-_track-6.mp3").unwrap();
+        assert_eq!(5, favorites(&fs).len());
+
+        fs.unlink("alice/favorites/bob_-_track-5.mp3").unwrap();
+        assert!(!favorites(&fs).contains(&"bob_-_track-5.mp3".to_string()));
+        assert_eq!(
+            Err(libc::ENOENT),
+            fs.unlink("alice/favorites/bob_-_track-5.mp3")
+        );
+    }
+
+    #[test]
+    fn fs_like_duplicate_names() {
+        let server = MockServer::start();
+        let filenames = Filenames {
+            tracks: Template::parse("{title}.mp3").unwrap(),
+            favorites: Template::parse("{genre}.mp3").unwrap(),
+            ..Filenames::default()
+        };
+        let root = root_with_filenames(
+            server.login("alice"),
+            vec![UserOptions::new("alice")],
+            filenames,
+        );
+        let fs = Harness::new(&root);
+        let favorites = |fs: &Harness<_>| -> Vec<String> {
+            let list = fs.list("alice/favorites").unwrap();
+            list.into_iter().map(|e| e.name).collect()
+        };
+        assert_eq!(
+            vec!["Electronic (201).mp3", "Electronic (202).mp3"],
+            favorites(&fs)
+        );
+
+        // 101 is the id of "first song", so this can only be a duplicate of "track 0".
+        assert!(fs.create("alice/favorites/track 0 (101).mp3").is_err());
+        assert!(fs
+            .symlink("../../bob/track 0 (101).mp3", "alice/favorites/x")
+            .is_err());
+        assert_eq!(2, favorites(&fs).len());
+
+        fs.create("alice/favorites/Electronic (205).mp3").unwrap();
+        assert!(favorites(&fs).contains(&"Electronic (205).mp3".to_string()));
+    }
+
+    #[test]
+    fn fs_like_not_permitted() {
+        let server = MockServer::start();
+        let fs = Harness::new(&test_root(&server));
+        assert_eq!(
+            Err(libc::EROFS),
+            fs.create("alice/favorites/bob_-_track-6.mp3").map(|_| ())
+        );
+
+        let fs = Harness::new(&root_with_client(server.login("bob")));
+        assert_eq!(
+            Err(libc::EPERM),
+            fs.unlink("alice/favorites/bob_-_track-0.mp3")
+        );
+        // Other directories can not be modified.
+        assert_eq!(Err(libc::EROFS), fs.unlink("alice/first-song.mp3"));
+    }
+
+    #[test]
+    fn fs_follow() {
+        let server = MockServer::start();
+        let root = root_with_client(server.login("alice"));
+        let fs = Harness::new(&CacheRoot::new(&root, CacheConfig::default()));
+        let following = |fs: &Harness<_>| -> Vec<String> {
+            let list = fs.list("alice/following").unwrap();
+            list.into_iter().map(|e| e.name).collect()
+        };
+        assert_eq!(vec!["bob"], following(&fs));
+
+        fs.unlink("alice/following/bob").unwrap();
+        assert!(following(&fs).is_empty());
+
+        // The user is followed, but listed as a symlink rather than the directory.
+        assert_eq!(
+            Err(libc::EEXIST),
+            fs.mkdir("alice/following/carol").map(|_| ())
+        );
+        assert_eq!(vec!["carol"], following(&fs));
+        assert_eq!(
+            fuse::FileType::Symlink,
+            fs.lookup("alice/following/carol").unwrap().kind
+        );
+        assert_eq!(
+            Err(libc::EPERM),
+            fs.symlink("../../bob", "alice/following/x").map(|_| ())
+        );
+
+        let attrs = fs.symlink("../../bob", "alice/following/bob").unwrap();
+        assert_eq!(attrs.kind, fs.getattr_ino(attrs.ino).unwrap().kind);
+        assert_eq!(vec!["carol", "bob"], following(&fs));
+        assert_eq!(
+            fuse::FileType::Symlink,
+            fs.lookup("alice/following/bob").unwrap().kind
+        );
+
+        assert_eq!(
+            Err(libc::ENOENT),
+            fs.mkdir("alice/following/nobody").map(|_| ())
+        );
+        // The logged in user is only looked up once.
+        let me = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("/me?"))
+            .count();
+        assert_eq!(1, me);
+        let fs = Harness::new(&root_with_client(server.login("bob")));
+        assert_eq!(Err(libc::EPERM), fs.unlink("alice/following/bob"));
+    }
+}
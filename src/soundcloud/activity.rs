@@ -0,0 +1,15 @@
+use super::{Playlist, Track};
+
+/// An item of the stream of a user, which is a track or playlist that was posted or reposted by
+/// someone the user follows.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum Activity {
+    #[serde(rename = "track", alias = "track-repost")]
+    Track { origin: Box<Track> },
+    #[serde(rename = "playlist", alias = "playlist-repost")]
+    Playlist { origin: Box<Playlist> },
+    /// Kinds of items that are not supported, which are skipped.
+    #[serde(other)]
+    Other,
+}
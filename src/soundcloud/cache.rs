@@ -0,0 +1,243 @@
+use crate::ioutil::fnv1a;
+use log::*;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A persistent cache for API responses.
+///
+/// Each response body is stored in its own file, keyed by the URL of the request. Entries that are
+/// older than the configured TTL are removed when they are read and when the cache is opened.
+///
+/// Clones of a DiskCache share the same state.
+#[derive(Clone, Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    ttl: Duration,
+    /// The files of the entries by their URL, so entries can be removed without reading every
+    /// file.
+    index: Arc<Mutex<HashMap<String, PathBuf>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    /// Seconds since the UNIX epoch at which the response was received.
+    fetched_at: u64,
+    body: String,
+}
+
+impl DiskCache {
+    /// Opens the cache in the specified directory. Entries that have expired are removed.
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> io::Result<DiskCache> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut index = HashMap::new();
+        for dirent in fs::read_dir(&dir)? {
+            let path = dirent?.path();
+            let entry = match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => read_entry(&path).ok(),
+                // Left behind by a write that was interrupted.
+                Some("tmp") => None,
+                _ => continue,
+            };
+            match entry {
+                Some(entry) if !expired(&entry, ttl) => {
+                    index.insert(entry.url, path);
+                }
+                _ => remove_file(&path),
+            }
+        }
+        Ok(DiskCache {
+            dir,
+            ttl,
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    /// Returns the directory in which soundcloud-fs stores cached data by default, following the
+    /// XDG base directory specification.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(base.join("soundcloud-fs"))
+    }
+
+    pub(crate) fn get(&self, url: &Url) -> Option<String> {
+        let key = cache_key(url);
+        let path = self.path_for_key(&key);
+        let entry = match read_entry(&path) {
+            Ok(v) => v,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("ignoring cache entry {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        // The file name is a hash of the URL, make sure this is not a collision.
+        if entry.url != key {
+            return None;
+        }
+        if expired(&entry, self.ttl) {
+            self.index.lock().unwrap().remove(&key);
+            remove_file(&path);
+            return None;
+        }
+        trace!("cache hit for {}", key);
+        Some(entry.body)
+    }
+
+    pub(crate) fn put(&self, url: &Url, body: &str) {
+        let key = cache_key(url);
+        let entry = Entry {
+            url: key.clone(),
+            fetched_at: unix_now(),
+            body: body.to_string(),
+        };
+        let path = self.path_for_key(&key);
+        // Write to a temporary file first so readers never observe a partially written entry.
+        let tmp_path = path.with_extension("tmp");
+        let rs = serde_json::to_vec(&entry)
+            .map_err(io::Error::from)
+            .and_then(|buf| fs::write(&tmp_path, buf))
+            .and_then(|_| fs::rename(&tmp_path, &path));
+        match rs {
+            Ok(()) => {
+                self.index.lock().unwrap().insert(key, path);
+            }
+            Err(err) => warn!("could not write cache entry {}: {}", path.display(), err),
+        }
+    }
+
+    /// Removes all entries of which the URL matches the predicate.
+    pub(crate) fn remove_matching(&self, pred: impl Fn(&Url) -> bool) {
+        let mut index = self.index.lock().unwrap();
+        index.retain(|url, path| {
+            if !Url::parse(url).map(|u| pred(&u)).unwrap_or(false) {
+                return true;
+            }
+            trace!("removing cache entry for {}", url);
+            remove_file(path);
+            false
+        });
+    }
+
+    fn path_for_key(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+    }
+}
+
+/// Derives the cache key for a URL. The client_id parameter is excluded because it changes every
+/// time a client is created.
+fn cache_key(url: &Url) -> String {
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != "client_id")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn read_entry(path: &Path) -> io::Result<Entry> {
+    let buf = fs::read(path)?;
+    serde_json::from_slice(&buf).map_err(io::Error::from)
+}
+
+fn expired(entry: &Entry, ttl: Duration) -> bool {
+    Duration::from_secs(unix_now().saturating_sub(entry.fetched_at)) >= ttl
+}
+
+fn remove_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            warn!("could not remove cache entry {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str, ttl: Duration) -> DiskCache {
+        let dir = env::temp_dir().join(format!("soundcloud-fs-test-{}-{}", name, unix_now()));
+        let _ = fs::remove_dir_all(&dir);
+        DiskCache::new(dir, ttl).unwrap()
+    }
+
+    #[test]
+    fn key_ignores_client_id() {
+        let a = Url::parse("https://api.soundcloud.com/users/1?client_id=a&limit=2").unwrap();
+        let b = Url::parse("https://api.soundcloud.com/users/1?client_id=b&limit=2").unwrap();
+        assert_eq!(cache_key(&a), cache_key(&b));
+        assert_eq!(cache_key(&a), "https://api.soundcloud.com/users/1?limit=2");
+    }
+
+    #[test]
+    fn put_get() {
+        let cache = temp_cache("put_get", Duration::from_secs(60));
+        let url = Url::parse("https://api.soundcloud.com/users/1?client_id=a").unwrap();
+        assert_eq!(None, cache.get(&url));
+        cache.put(&url, "{\"id\":1}");
+        assert_eq!(Some("{\"id\":1}".to_string()), cache.get(&url));
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn remove_matching() {
+        let cache = temp_cache("remove_matching", Duration::from_secs(60));
+        let a = Url::parse("https://api.soundcloud.com/users/1/favorites?offset=0").unwrap();
+        let b = Url::parse("https://api.soundcloud.com/users/1").unwrap();
+        cache.put(&a, "{}");
+        cache.put(&b, "{}");
+        cache.remove_matching(|url| url.path().ends_with("/favorites"));
+        assert_eq!(None, cache.get(&a));
+        assert_eq!(Some("{}".to_string()), cache.get(&b));
+
+        // Entries stored by a previous instance can be removed as well.
+        let cache = DiskCache::new(&cache.dir, Duration::from_secs(60)).unwrap();
+        cache.remove_matching(|url| url.path() == "/users/1");
+        assert_eq!(None, cache.get(&b));
+        assert_eq!(0, fs::read_dir(&cache.dir).unwrap().count());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn expired() {
+        let cache = temp_cache("expired", Duration::from_secs(0));
+        let url = Url::parse("https://api.soundcloud.com/users/1").unwrap();
+        cache.put(&url, "{}");
+        assert_eq!(None, cache.get(&url));
+        assert_eq!(0, fs::read_dir(&cache.dir).unwrap().count());
+
+        // Expired entries are removed when the cache is opened.
+        let cache = DiskCache::new(&cache.dir, Duration::from_secs(60)).unwrap();
+        cache.put(&url, "{}");
+        fs::write(cache.dir.join("0.tmp"), "").unwrap();
+        let cache = DiskCache::new(&cache.dir, Duration::from_secs(0)).unwrap();
+        assert_eq!(0, fs::read_dir(&cache.dir).unwrap().count());
+        assert!(cache.index.lock().unwrap().is_empty());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -6,6 +6,8 @@ use std::io;
 #[derive(Debug)]
 pub enum Error {
     Login,
+    /// The operation requires a client that is logged in.
+    Unauthenticated,
     ArtworkNotAvailable,
 
     IOError(io::Error),
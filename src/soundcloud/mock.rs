@@ -0,0 +1,749 @@
+//! A stand-in for the SoundCloud website and API that serves fixture data from a local port, so
+//! tests do not depend on the network.
+
+use super::track::AUDIO_CBR_BITRATE;
+use super::{BaseUrls, Client};
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const DATE: &str = "2019/01/02 03:04:05 +0000";
+/// The duration of every fixture track. The audio of a track is as large as the bitrate and the
+/// duration imply.
+const TRACK_DURATION_MS: u64 = 1000;
+
+/// Fixture data, indexed by ID.
+struct Fixtures {
+    users: Vec<Value>,
+    tracks: Vec<Value>,
+    playlists: Vec<Value>,
+    // Relations between users and other objects by ID.
+    user_tracks: HashMap<i64, Vec<i64>>,
+    favorites: HashMap<i64, Vec<i64>>,
+    followings: HashMap<i64, Vec<i64>>,
+    user_playlists: HashMap<i64, Vec<i64>>,
+    private_tracks: HashMap<i64, Vec<i64>>,
+    playlist_likes: HashMap<i64, Vec<i64>>,
+    play_history: HashMap<i64, Vec<i64>>,
+    // Reposts and stream items are tracks or playlists, identified by the kind and ID.
+    reposts: HashMap<i64, Vec<(&'static str, i64)>>,
+    stream: HashMap<i64, Vec<(&'static str, i64)>>,
+}
+
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status: "200 OK",
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    fn json(value: &Value) -> Self {
+        Response::ok("application/json", value.to_string())
+    }
+
+    fn unauthorized() -> Self {
+        Response {
+            status: "401 Unauthorized",
+            headers: Vec::new(),
+            body: b"{}".to_vec(),
+        }
+    }
+
+    fn not_found() -> Self {
+        Response {
+            status: "404 Not Found",
+            headers: Vec::new(),
+            body: b"{}".to_vec(),
+        }
+    }
+}
+
+/// The fixtures served are:
+///
+/// * alice (1): tracks first-song (101), second-song (102) and third-song (103), favorites bob's
+///   tracks 201 and 202, follows bob and has the playlist mixtape (301) of tracks 101 and 201.
+///   Reposts bob's track 205, bob's playlist b-sides (302) and its own track 102. Has the
+///   private track secret-song (104), likes b-sides, has played 202, 101 and 202 again and has
+///   bob's tracks 210 (twice) and 201 and b-sides in its stream.
+/// * bob (2): 250 tracks (201 and up) so listings need more than one page and the playlist
+///   b-sides (302) of tracks 203 and 204.
+/// * carol (3): has nothing.
+///
+/// Requests with the token of a user are authenticated as that user, see `login`.
+pub struct MockServer {
+    base: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    pub fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let fixtures = Arc::new(Mutex::new(Fixtures::new(&base)));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let server = MockServer {
+            base: base.clone(),
+            requests: requests.clone(),
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                let base = base.clone();
+                let fixtures = fixtures.clone();
+                let requests = requests.clone();
+                thread::spawn(move || {
+                    let _ = handle_connection(stream, &base, &fixtures, &requests);
+                });
+            }
+        });
+        server
+    }
+
+    pub fn urls(&self) -> BaseUrls {
+        BaseUrls {
+            web: self.base.clone(),
+            api: self.base.clone(),
+            api_v2: self.base.clone(),
+        }
+    }
+
+    /// Returns an anonymous client that talks to this server.
+    pub fn client(&self) -> Client {
+        Client::anonymous_with_urls(self.urls()).unwrap()
+    }
+
+    /// Returns a client that is logged in as the user with the specified permalink.
+    pub fn login(&self, permalink: &str) -> Client {
+        Client::from_token(
+            self.urls(),
+            "mock-client-id",
+            format!("token-{}", permalink),
+        )
+        .unwrap()
+    }
+
+    /// Returns the URL of the specified path on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    /// Returns the paths, including the query, of all requests made so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the audio that is served for the specified track.
+    pub fn audio(track_id: i64) -> Vec<u8> {
+        let len = TRACK_DURATION_MS * AUDIO_CBR_BITRATE / 1000 / 8;
+        (0..len).map(|i| (i as i64 ^ track_id) as u8).collect()
+    }
+}
+
+impl Fixtures {
+    fn new(base: &str) -> Self {
+        let mut f = Fixtures {
+            users: vec![
+                user_json(base, 1, "alice"),
+                user_json(base, 2, "bob"),
+                user_json(base, 3, "carol"),
+            ],
+            tracks: Vec::new(),
+            playlists: Vec::new(),
+            user_tracks: HashMap::new(),
+            favorites: HashMap::new(),
+            followings: HashMap::new(),
+            user_playlists: HashMap::new(),
+            private_tracks: HashMap::new(),
+            playlist_likes: HashMap::new(),
+            play_history: HashMap::new(),
+            reposts: HashMap::new(),
+            stream: HashMap::new(),
+        };
+        for (id, permalink) in [
+            (101, "first-song"),
+            (102, "second-song"),
+            (103, "third-song"),
+        ]
+        .iter()
+        .cloned()
+        {
+            f.add_track(base, 1, id, permalink);
+        }
+        for i in 0..250 {
+            f.add_track(base, 2, 201 + i, &format!("track-{}", i));
+        }
+        f.favorites.insert(1, vec![201, 202]);
+        f.followings.insert(1, vec![2]);
+
+        f.add_playlist(base, 1, 301, "mixtape", &[101, 201]);
+        f.add_playlist(base, 2, 302, "b-sides", &[203, 204]);
+        f.reposts.insert(
+            1,
+            vec![
+                ("track-repost", 205),
+                ("playlist-repost", 302),
+                ("track-repost", 102),
+            ],
+        );
+
+        f.add_track(base, 1, 104, "secret-song");
+        f.user_tracks.get_mut(&1).unwrap().retain(|id| *id != 104);
+        f.private_tracks.insert(1, vec![104]);
+        f.tracks.last_mut().unwrap()["sharing"] = json!("private");
+        f.playlist_likes.insert(1, vec![302]);
+        f.play_history.insert(1, vec![202, 101, 202]);
+        f.stream.insert(
+            1,
+            vec![
+                ("track", 210),
+                ("playlist-repost", 302),
+                ("track-repost", 201),
+                ("track", 210),
+            ],
+        );
+
+        f.update_counts();
+        f
+    }
+
+    fn add_track(&mut self, base: &str, user_id: i64, id: i64, permalink: &str) {
+        let user = self.user(&user_id.to_string()).unwrap();
+        let user_permalink = user["permalink"].as_str().unwrap().to_string();
+        self.tracks
+            .push(track_json(base, user_id, &user_permalink, id, permalink));
+        self.user_tracks.entry(user_id).or_default().push(id);
+    }
+
+    fn add_playlist(&mut self, base: &str, user_id: i64, id: i64, permalink: &str, tracks: &[i64]) {
+        let user = self.user(&user_id.to_string()).unwrap();
+        let user_permalink = user["permalink"].as_str().unwrap().to_string();
+        let tracks: Vec<_> = tracks
+            .iter()
+            .map(|id| self.track(*id).unwrap().clone())
+            .collect();
+        self.playlists.push(json!({
+            "kind": "playlist",
+            "id": id,
+            "created_at": DATE,
+            "last_modified": DATE,
+            "user_id": user_id,
+            "duration": TRACK_DURATION_MS * tracks.len() as u64,
+            "sharing": "public",
+            "tag_list": "",
+            "permalink": permalink,
+            "track_count": tracks.len(),
+            "title": permalink.replace('-', " "),
+            "license": "all-rights-reserved",
+            "uri": format!("{}/playlists/{}", base, id),
+            "permalink_url": format!("{}/{}/sets/{}", base, user_permalink, permalink),
+            "user": track_user_json(base, user_id, &user_permalink),
+            "tracks": tracks,
+        }));
+        self.user_playlists.entry(user_id).or_default().push(id);
+    }
+
+    fn update_counts(&mut self) {
+        let counts: Vec<_> = self
+            .users
+            .iter()
+            .map(|u| {
+                let id = u["id"].as_i64().unwrap();
+                let len = |m: &HashMap<i64, Vec<i64>>| m.get(&id).map(Vec::len).unwrap_or(0);
+                (
+                    id,
+                    len(&self.user_tracks),
+                    len(&self.favorites),
+                    len(&self.followings),
+                    len(&self.user_playlists),
+                    len(&self.private_tracks),
+                    self.followers(id).len(),
+                )
+            })
+            .collect();
+        for (id, tracks, favorites, followings, playlists, private, followers) in counts {
+            let user = self.user_mut(id);
+            user["track_count"] = json!(tracks);
+            user["private_tracks_count"] = json!(private);
+            user["public_favorites_count"] = json!(favorites);
+            user["followings_count"] = json!(followings);
+            user["followers_count"] = json!(followers);
+            user["playlist_count"] = json!(playlists);
+        }
+    }
+
+    /// Returns the IDs of the users that follow the specified user.
+    fn followers(&self, id: i64) -> Vec<i64> {
+        let mut followers: Vec<_> = self
+            .followings
+            .iter()
+            .filter(|(_, followings)| followings.contains(&id))
+            .map(|(follower, _)| *follower)
+            .collect();
+        followers.sort_unstable();
+        followers
+    }
+
+    /// Looks up a user by ID or permalink.
+    fn user(&self, key: &str) -> Option<&Value> {
+        self.users.iter().find(|u| {
+            u["id"].as_i64().map(|id| id.to_string()).as_deref() == Some(key)
+                || u["permalink"] == key
+        })
+    }
+
+    fn user_mut(&mut self, id: i64) -> &mut Value {
+        self.users.iter_mut().find(|u| u["id"] == id).unwrap()
+    }
+
+    fn track(&self, id: i64) -> Option<&Value> {
+        self.tracks.iter().find(|t| t["id"] == id)
+    }
+
+    fn playlist(&self, id: i64) -> Option<&Value> {
+        self.playlists.iter().find(|t| t["id"] == id)
+    }
+
+    /// Handles requests that modify the fixtures on behalf of the authenticated user.
+    fn route_write(&mut self, method: &str, path: &str, me: Option<i64>) -> Response {
+        let me = match me {
+            Some(v) => v,
+            None => return Response::unauthorized(),
+        };
+        let segments: Vec<_> = path.trim_start_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            (_, ["me", "favorites", id]) => {
+                let id: i64 = match id.parse() {
+                    Ok(id) if self.track(id).is_some() => id,
+                    _ => return Response::not_found(),
+                };
+                let favorites = self.favorites.entry(me).or_default();
+                favorites.retain(|t| *t != id);
+                if method == "PUT" {
+                    favorites.insert(0, id);
+                }
+            }
+            (_, ["me", "followings", id]) => {
+                let id: i64 = match self.user(id) {
+                    Some(user) => user["id"].as_i64().unwrap(),
+                    None => return Response::not_found(),
+                };
+                let followings = self.followings.entry(me).or_default();
+                followings.retain(|u| *u != id);
+                if method == "PUT" {
+                    followings.push(id);
+                }
+            }
+            _ => return Response::not_found(),
+        }
+        self.update_counts();
+        Response::json(&json!({}))
+    }
+
+    fn route(
+        &self,
+        base: &str,
+        path: &str,
+        query: &HashMap<String, String>,
+        me: Option<i64>,
+    ) -> Response {
+        let segments: Vec<_> = path.trim_start_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["me"] => match me.and_then(|id| self.user(&id.to_string())) {
+                Some(user) => Response::json(user),
+                None => Response::unauthorized(),
+            },
+            ["me", ..] if me.is_none() => Response::unauthorized(),
+            ["me", "tracks"] => {
+                let mut items = self.lookup(&self.user_tracks, me.unwrap(), |id| self.track(id));
+                items.extend(self.lookup(&self.private_tracks, me.unwrap(), |id| self.track(id)));
+                Response::json(&page(base, path, query, items))
+            }
+            ["me", "likes", "playlists"] => {
+                let items = self.lookup(&self.playlist_likes, me.unwrap(), |id| self.playlist(id));
+                Response::json(&page(base, path, query, items))
+            }
+            ["me", "play-history", "tracks"] => {
+                let items = self
+                    .lookup(&self.play_history, me.unwrap(), |id| self.track(id))
+                    .into_iter()
+                    .map(|track| json!({ "played_at": 0, "track": track }))
+                    .collect();
+                Response::json(&page(base, path, query, items))
+            }
+            ["me", "activities"] => {
+                let items = self.items(&self.stream, me.unwrap(), Some("origin"));
+                Response::json(&page(base, path, query, items))
+            }
+            ["discover"] => Response::ok(
+                "text/html",
+                format!(
+                    "<html><script crossorigin src=\"{}/assets/app.js\"></script></html>",
+                    base
+                ),
+            ),
+            ["assets", "app.js"] => Response::ok(
+                "application/javascript",
+                "var a={client_id:\"mock-client-id\"};",
+            ),
+            ["users", key] => match self.user(key) {
+                Some(user) => Response::json(user),
+                None => Response::not_found(),
+            },
+            ["users", key, collection] => {
+                let id = match self.user(key) {
+                    Some(user) => user["id"].as_i64().unwrap(),
+                    None => return Response::not_found(),
+                };
+                let items: Vec<Value> = match *collection {
+                    "tracks" => self.lookup(&self.user_tracks, id, |id| self.track(id)),
+                    "favorites" => self.lookup(&self.favorites, id, |id| self.track(id)),
+                    "followings" => {
+                        self.lookup(&self.followings, id, |id| self.user(&id.to_string()))
+                    }
+                    "followers" => {
+                        let followers = self.followers(id);
+                        followers
+                            .iter()
+                            .filter_map(|id| self.user(&id.to_string()).cloned())
+                            .collect()
+                    }
+                    "playlists" => self.lookup(&self.user_playlists, id, |id| self.playlist(id)),
+                    "reposts" => self.items(&self.reposts, id, None),
+                    _ => return Response::not_found(),
+                };
+                Response::json(&page(base, path, query, items))
+            }
+            ["users", key, "tracks", permalink] => {
+                let user_permalink = match self.user(key) {
+                    Some(user) => user["permalink"].clone(),
+                    None => return Response::not_found(),
+                };
+                let track = self.tracks.iter().find(|t| {
+                    t["permalink"] == *permalink && t["user"]["permalink"] == user_permalink
+                });
+                match track {
+                    Some(track) => Response::json(track),
+                    None => Response::not_found(),
+                }
+            }
+            // Searches match the query against the title or name of the public items.
+            [collection @ "tracks"] | [collection @ "users"] | [collection @ "playlists"] => {
+                let q = query.get("q").map(|q| q.to_lowercase()).unwrap_or_default();
+                let (items, field) = match *collection {
+                    "tracks" => (&self.tracks, "title"),
+                    "users" => (&self.users, "username"),
+                    _ => (&self.playlists, "title"),
+                };
+                let items = items
+                    .iter()
+                    .filter(|item| item["sharing"] != "private")
+                    .filter(|item| {
+                        let name = item[field].as_str().unwrap_or("").to_lowercase();
+                        name.contains(&q)
+                    })
+                    .cloned()
+                    .collect();
+                Response::json(&page(base, path, query, items))
+            }
+            ["resolve"] => {
+                let url = query.get("url").map(String::as_str).unwrap_or("");
+                let path = url.strip_prefix(base).unwrap_or("");
+                let segments: Vec<_> = path.trim_start_matches('/').split('/').collect();
+                let item =
+                    match segments.as_slice() {
+                        [user] => self.user(user),
+                        [user, "sets", permalink] => self.playlists.iter().find(|p| {
+                            p["permalink"] == *permalink && p["user"]["permalink"] == *user
+                        }),
+                        [user, permalink] => self.tracks.iter().find(|t| {
+                            t["permalink"] == *permalink && t["user"]["permalink"] == *user
+                        }),
+                        _ => None,
+                    };
+                match item {
+                    Some(item) => Response::json(item),
+                    None => Response::not_found(),
+                }
+            }
+            ["tracks", id] => match id.parse().ok().and_then(|id| self.track(id)) {
+                Some(track) => Response::json(track),
+                None => Response::not_found(),
+            },
+            ["tracks", id, "stream", "hls"] => Response::json(&json!({
+                "url": format!("{}/hls/{}.m3u8", base, id),
+            })),
+            ["hls", file] => {
+                let id: i64 = match file.trim_end_matches(".m3u8").parse() {
+                    Ok(v) => v,
+                    Err(_) => return Response::not_found(),
+                };
+                let len = MockServer::audio(id).len();
+                let m3u = format!(
+                    "#EXTM3U\n#EXTINF:0.5,\n{base}/media/0/{half}/{id}/audio.mp3\n#EXTINF:0.5,\n{base}/media/{half}/{len}/{id}/audio.mp3\n#EXT-X-ENDLIST\n",
+                    base = base,
+                    half = len / 2,
+                    len = len,
+                    id = id,
+                );
+                Response::ok("application/vnd.apple.mpegurl", m3u)
+            }
+            ["media", _, _, id, "audio.mp3"] => match id.parse() {
+                Ok(id) => Response::ok("audio/mpeg", MockServer::audio(id)),
+                Err(_) => Response::not_found(),
+            },
+            // Similar to httpbin.org/range/<n>: n bytes of the repeating alphabet.
+            ["range", n] => match n.parse::<usize>() {
+                Ok(n) => Response::ok(
+                    "application/octet-stream",
+                    (b'a'..=b'z').cycle().take(n).collect::<Vec<_>>(),
+                ),
+                Err(_) => Response::not_found(),
+            },
+            // The page of a track on the website.
+            [user, permalink] => {
+                let track = self
+                    .tracks
+                    .iter()
+                    .find(|t| t["permalink"] == *permalink && t["user"]["permalink"] == *user);
+                match track {
+                    Some(track) => Response::ok(
+                        "text/html",
+                        format!(
+                            "<html><script>var c={{\"url\":\"{}/tracks/{}/stream/hls\"}};</script></html>",
+                            base, track["id"]
+                        ),
+                    ),
+                    None => Response::not_found(),
+                }
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    /// Looks up the tracks and playlists of a relation and wraps them in objects tagged with the
+    /// kind of the item. The item is put in `field`, or in a field named after the kind if unset.
+    fn items(
+        &self,
+        relation: &HashMap<i64, Vec<(&'static str, i64)>>,
+        id: i64,
+        field: Option<&str>,
+    ) -> Vec<Value> {
+        relation
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter_map(|(kind, id)| {
+                let base_kind = kind.trim_end_matches("-repost");
+                let item = match base_kind {
+                    "track" => self.track(*id)?,
+                    _ => self.playlist(*id)?,
+                };
+                let mut value = json!({ "type": kind, "created_at": DATE });
+                value[field.unwrap_or(base_kind)] = item.clone();
+                Some(value)
+            })
+            .collect()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        relation: &HashMap<i64, Vec<i64>>,
+        id: i64,
+        get: impl Fn(i64) -> Option<&'a Value>,
+    ) -> Vec<Value> {
+        relation
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| get(*id).cloned())
+            .collect()
+    }
+}
+
+fn user_json(base: &str, id: i64, permalink: &str) -> Value {
+    json!({
+        "kind": "user",
+        "id": id,
+        "permalink": permalink,
+        "username": permalink,
+        "last_modified": DATE,
+        "uri": format!("{}/users/{}", base, id),
+        "permalink_url": format!("{}/{}", base, permalink),
+        "avatar_url": format!("{}/avatars/{}.jpg", base, id),
+        "full_name": format!("{} {}", permalink, permalink),
+        "online": false,
+        "track_count": 0,
+        "playlist_count": 0,
+        "followers_count": 0,
+        "followings_count": 0,
+        "public_favorites_count": 0,
+    })
+}
+
+/// Returns a track as served by the API. This is also useful to tests that need a track without
+/// running a server.
+pub fn track_json(
+    base: &str,
+    user_id: i64,
+    user_permalink: &str,
+    id: i64,
+    permalink: &str,
+) -> Value {
+    json!({
+        "kind": "track",
+        "id": id,
+        "created_at": DATE,
+        "user_id": user_id,
+        "duration": TRACK_DURATION_MS,
+        "commentable": true,
+        "state": "finished",
+        "original_content_size": 1234,
+        "last_modified": DATE,
+        "sharing": "public",
+        "tag_list": "",
+        "permalink": permalink,
+        "streamable": true,
+        "embeddable_by": "all",
+        "downloadable": false,
+        "genre": "Electronic",
+        "title": permalink.replace('-', " "),
+        "license": "all-rights-reserved",
+        "uri": format!("{}/tracks/{}", base, id),
+        "user": track_user_json(base, user_id, user_permalink),
+        "permalink_url": format!("{}/{}/{}", base, user_permalink, permalink),
+    })
+}
+
+fn track_user_json(base: &str, id: i64, permalink: &str) -> Value {
+    json!({
+        "id": id,
+        "permalink": permalink,
+        "username": permalink,
+        "last_modified": DATE,
+        "uri": format!("{}/users/{}", base, id),
+        "permalink_url": format!("{}/{}", base, permalink),
+        "avatar_url": format!("{}/avatars/{}.jpg", base, id),
+    })
+}
+
+/// Slices a collection according to the limit and offset query parameters.
+fn page(base: &str, path: &str, query: &HashMap<String, String>, items: Vec<Value>) -> Value {
+    let param = |k: &str, default| query.get(k).and_then(|v| v.parse().ok()).unwrap_or(default);
+    let limit: usize = param("limit", 50);
+    let offset: usize = param("offset", 0);
+    let end = items.len().min(offset + limit);
+    let next_href = if end < items.len() {
+        json!(format!(
+            "{}{}?linked_partitioning=1&limit={}&offset={}",
+            base, path, limit, end
+        ))
+    } else {
+        Value::Null
+    };
+    json!({
+        "collection": items.get(offset..end).unwrap_or(&[]),
+        "next_href": next_href,
+    })
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    base: &str,
+    fixtures: &Mutex<Fixtures>,
+    requests: &Mutex<Vec<String>>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut request_line = request_line.split_whitespace();
+    let method = request_line.next().unwrap_or("GET").to_string();
+    let target = request_line.next().unwrap_or("/").to_string();
+
+    let mut range_start = None;
+    let mut content_length = 0;
+    let mut token = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        let mut kv = line.splitn(2, ':');
+        let key = kv.next().unwrap_or("").trim().to_lowercase();
+        let value = kv.next().unwrap_or("").trim();
+        match key.as_str() {
+            "range" => {
+                range_start = value
+                    .trim_start_matches("bytes=")
+                    .trim_end_matches('-')
+                    .parse::<usize>()
+                    .ok();
+            }
+            "content-length" => content_length = value.parse().unwrap_or(0),
+            "authorization" => token = Some(value.trim_start_matches("OAuth ").to_string()),
+            _ => (),
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    requests.lock().unwrap().push(target.clone());
+    let url = Url::parse(&format!("{}{}", base, target)).unwrap();
+    let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let mut fixtures = fixtures.lock().unwrap();
+    let me = token
+        .as_ref()
+        .and_then(|t| fixtures.user(t.trim_start_matches("token-")))
+        .map(|u| u["id"].as_i64().unwrap());
+    let mut res = match method.as_str() {
+        "GET" => fixtures.route(base, url.path(), &query, me),
+        _ => fixtures.route_write(&method, url.path(), me),
+    };
+    drop(fixtures);
+
+    if let (Some(start), "200 OK") = (range_start, res.status) {
+        let total = res.body.len();
+        if start >= total && total > 0 {
+            res = Response {
+                status: "416 Range Not Satisfiable",
+                headers: vec![("Content-Range", format!("bytes */{}", total))],
+                body: Vec::new(),
+            };
+        } else {
+            res.status = "206 Partial Content";
+            res.headers.push((
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, total.saturating_sub(1), total),
+            ));
+            res.body.drain(..start.min(total));
+        }
+    }
+
+    let mut stream = stream;
+    write!(stream, "HTTP/1.1 {}\r\n", res.status)?;
+    for (k, v) in &res.headers {
+        write!(stream, "{}: {}\r\n", k, v)?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        res.body.len()
+    )?;
+    stream.write_all(&res.body)?;
+    stream.flush()
+}
@@ -1,10 +1,17 @@
+mod activity;
+mod cache;
 mod error;
 mod format;
+#[cfg(test)]
+pub(crate) mod mock;
+mod playlist;
+mod repost;
+mod resolve;
 mod track;
 mod user;
 mod util;
 
-use self::util::http::retry_execute;
+use self::util::http::{self, retry_execute};
 use lazy_static::lazy_static;
 use log::*;
 use rayon::prelude::*;
@@ -16,9 +23,15 @@ use std::fmt;
 use std::str;
 use url;
 
+pub use self::activity::Activity;
+pub use self::cache::DiskCache;
 pub use self::error::Error;
-pub use self::track::Track;
+pub use self::playlist::Playlist;
+pub use self::repost::Repost;
+pub use self::resolve::Resource;
+pub use self::track::{Track, TrackUser};
 pub use self::user::User;
+pub use self::util::http::PrefetchStats;
 
 const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:71.0) Gecko/20100101 Firefox/71.0";
 const PAGE_MAX_SIZE: u64 = 200;
@@ -50,11 +63,37 @@ pub(crate) fn default_client() -> &'static blocking::Client {
     &DEFAULT_CLIENT
 }
 
+/// The locations of the services the client talks to. These only need to be changed to point the
+/// client at something other than SoundCloud itself, like a test server.
+#[derive(Clone, Debug)]
+pub struct BaseUrls {
+    /// The website, e.g. "https://soundcloud.com"
+    pub web: String,
+    /// The public API, e.g. "https://api.soundcloud.com"
+    pub api: String,
+    /// The API used by the web application, e.g. "https://api-v2.soundcloud.com"
+    pub api_v2: String,
+}
+
+impl Default for BaseUrls {
+    fn default() -> Self {
+        BaseUrls {
+            web: "https://soundcloud.com".to_string(),
+            api: "https://api.soundcloud.com".to_string(),
+            api_v2: "https://api-v2.soundcloud.com".to_string(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
     client: blocking::Client,
+    urls: BaseUrls,
     client_id: String,
     token: Option<String>,
+    cache: Option<DiskCache>,
+    read_ahead: u64,
+    resume_retries: u32,
 }
 
 impl Client {
@@ -63,8 +102,16 @@ impl Client {
     ///
     /// This login method is not guaranteed to be stable!
     pub fn login(username: impl AsRef<str>, password: impl AsRef<str>) -> Result<Client, Error> {
+        Client::login_with_urls(BaseUrls::default(), username, password)
+    }
+
+    pub fn login_with_urls(
+        urls: BaseUrls,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+    ) -> Result<Client, Error> {
         let client = default_client();
-        let client_id = anonymous_client_id(&client)?;
+        let client_id = anonymous_client_id(&client, &urls)?;
 
         let token = {
             trace!("performing password login with user: {}", username.as_ref());
@@ -82,9 +129,12 @@ impl Client {
                 user_agent: USER_AGENT,
             };
             let login_url = Url::parse_with_params(
-                "https://api-v2.soundcloud.com/sign-in/password?app_version=1541509103&app_locale=en",
+                &format!(
+                    "{}/sign-in/password?app_version=1541509103&app_locale=en",
+                    urls.api_v2
+                ),
                 &[("client_id", &client_id)],
-            ).unwrap();
+            )?;
             trace!("password login URL: {}", login_url);
             let login_res_body: PasswordLoginResBody = retry_execute(
                 client,
@@ -96,21 +146,33 @@ impl Client {
         };
 
         trace!("SoundCloud login got token: {}****", &token[0..4]);
-        Client::from_token(client_id, token)
+        Client::from_token(urls, client_id, token)
     }
 
     // Attempt to create a client with read-only access to the public API.
     pub fn anonymous() -> Result<Client, Error> {
+        Client::anonymous_with_urls(BaseUrls::default())
+    }
+
+    pub fn anonymous_with_urls(urls: BaseUrls) -> Result<Client, Error> {
         let client = default_client();
-        let client_id = anonymous_client_id(&client)?;
+        let client_id = anonymous_client_id(&client, &urls)?;
         Ok(Client {
             client: client.clone(),
+            urls,
             client_id,
             token: None,
+            cache: None,
+            read_ahead: 0,
+            resume_retries: http::DEFAULT_RESUME_RETRIES,
         })
     }
 
-    fn from_token(client_id: impl Into<String>, token: impl Into<String>) -> Result<Client, Error> {
+    fn from_token(
+        urls: BaseUrls,
+        client_id: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Result<Client, Error> {
         let token = token.into();
         let auth_client = blocking::Client::builder()
             .default_headers({
@@ -122,11 +184,167 @@ impl Client {
             .build()?;
         Ok(Client {
             client: auth_client,
+            urls,
             client_id: client_id.into(),
             token: Some(token),
+            cache: None,
+            read_ahead: 0,
+            resume_retries: http::DEFAULT_RESUME_RETRIES,
         })
     }
 
+    /// Sets the number of bytes of audio that is fetched ahead of the reader in the background.
+    pub fn with_read_ahead(mut self, bytes: u64) -> Client {
+        self.read_ahead = bytes;
+        self
+    }
+
+    /// Sets the number of times an interrupted audio download is resumed before reading fails.
+    pub fn with_resume_retries(mut self, retries: u32) -> Client {
+        self.resume_retries = retries;
+        self
+    }
+
+    /// Makes the client look up the responses of metadata queries in the specified cache before
+    /// querying the API.
+    pub fn with_cache(mut self, cache: DiskCache) -> Client {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Returns whether the client is logged in as a user.
+    pub fn is_authenticated(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Returns the user the client is logged in as.
+    pub fn me(&self) -> Result<User, Error> {
+        if self.token.is_none() {
+            return Err(Error::Unauthenticated);
+        }
+        self.query(Method::GET, self.api_url("/me"))
+    }
+
+    /// Returns the most recent items of the stream of the user the client is logged in as. The
+    /// stream is endless, so at most `limit` items are fetched.
+    pub fn stream(&self, limit: usize) -> Result<Vec<Activity>, Error> {
+        if self.token.is_none() {
+            return Err(Error::Unauthenticated);
+        }
+        let activities: Vec<Activity> =
+            Page::all_linked(self, self.api_url("/me/activities"), limit)?;
+        Ok(activities
+            .into_iter()
+            .filter(|activity| !matches!(activity, Activity::Other))
+            .collect())
+    }
+
+    /// Returns the playlists liked by the user the client is logged in as.
+    pub fn liked_playlists(&self) -> Result<Vec<Playlist>, Error> {
+        if self.token.is_none() {
+            return Err(Error::Unauthenticated);
+        }
+        Page::all_linked(self, self.api_url("/me/likes/playlists"), usize::MAX)
+    }
+
+    /// Returns the tracks most recently played by the user the client is logged in as, most
+    /// recent first. At most `limit` plays are fetched.
+    pub fn play_history(&self, limit: usize) -> Result<Vec<Track>, Error> {
+        if self.token.is_none() {
+            return Err(Error::Unauthenticated);
+        }
+        let plays: Vec<Play> =
+            Page::all_linked(self, self.api_url("/me/play-history/tracks"), limit)?;
+        Ok(plays.into_iter().map(|play| play.track).collect())
+    }
+
+    /// Returns the private tracks of the user the client is logged in as, which are not listed
+    /// with the tracks of the user.
+    pub fn private_tracks(&self) -> Result<Vec<Track>, Error> {
+        let me = self.me()?;
+        let count = me.track_count + me.private_tracks_count.unwrap_or(0);
+        let tracks: Vec<Track> =
+            Page::all_with_size_hint(self, self.api_url("/me/tracks"), count as u64)?;
+        Ok(tracks
+            .into_iter()
+            .filter(|track| track.sharing == "private")
+            .collect())
+    }
+
+    /// Looks up the track, user or playlist of a URL of the website, e.g.
+    /// "https://soundcloud.com/bryan/sbahn-sounds". A URL without a scheme is taken to be a path
+    /// on the website, e.g. "bryan/sets/field-recordings".
+    pub fn resolve(&self, url: &str) -> Result<Resource, Error> {
+        let url = if url.contains("://") {
+            url.to_string()
+        } else {
+            format!("{}/{}", self.urls.web, url.trim_start_matches('/'))
+        };
+        let url = Url::parse_with_params(&self.api_url("/resolve"), &[("url", url)])?;
+        self.query(Method::GET, url)
+    }
+
+    /// Searches for tracks matching the query. At most `limit` results are fetched.
+    pub fn search_tracks(&self, query: &str, limit: usize) -> Result<Vec<Track>, Error> {
+        self.search("tracks", query, limit)
+    }
+
+    /// Searches for users matching the query. At most `limit` results are fetched.
+    pub fn search_users(&self, query: &str, limit: usize) -> Result<Vec<User>, Error> {
+        self.search("users", query, limit)
+    }
+
+    /// Searches for playlists matching the query. At most `limit` results are fetched.
+    pub fn search_playlists(&self, query: &str, limit: usize) -> Result<Vec<Playlist>, Error> {
+        self.search("playlists", query, limit)
+    }
+
+    fn search<T: DeserializeOwned + Send>(
+        &self,
+        collection: &str,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<T>, Error> {
+        let url =
+            Url::parse_with_params(&self.api_url(format!("/{}", collection)), &[("q", query)])?;
+        Page::all_linked(self, url, limit)
+    }
+
+    /// Adds a track to the favorites of the user the client is logged in as.
+    pub fn like(&self, track_id: i64) -> Result<(), Error> {
+        self.update_collection(Method::PUT, "favorites", track_id)
+    }
+
+    /// Removes a track from the favorites of the user the client is logged in as.
+    pub fn unlike(&self, track_id: i64) -> Result<(), Error> {
+        self.update_collection(Method::DELETE, "favorites", track_id)
+    }
+
+    /// Adds (PUT) or removes (DELETE) an item of a collection of the user the client is logged in
+    /// as, like its favorites or followings.
+    pub(crate) fn update_collection(
+        &self,
+        method: Method,
+        collection: &str,
+        id: i64,
+    ) -> Result<(), Error> {
+        if self.token.is_none() {
+            return Err(Error::Unauthenticated);
+        }
+        self.query_string(method, self.api_url(format!("/me/{}/{}", collection, id)))?;
+        // Cached listings of the collection are now outdated.
+        if let Some(cache) = &self.cache {
+            let suffix = format!("/{}", collection);
+            cache.remove_matching(|url| url.path().ends_with(&suffix));
+        }
+        Ok(())
+    }
+
+    /// Returns the URL of the specified path in the public API.
+    pub(crate) fn api_url(&self, path: impl AsRef<str>) -> String {
+        format!("{}{}", self.urls.api, path.as_ref())
+    }
+
     pub(crate) fn request(
         &self,
         method: reqwest::Method,
@@ -150,12 +368,69 @@ impl Client {
         Ok(s)
     }
 
+    /// Queries the API and deserializes the JSON response. Responses to GET requests for users,
+    /// tracks and playlists are cached if the client has a cache and is anonymous, see
+    /// Client::is_cacheable.
     pub(crate) fn query<T: DeserializeOwned>(
         &self,
         method: reqwest::Method,
         base_url: impl AsRef<str>,
+    ) -> Result<T, Error> {
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| method == Method::GET && self.is_cacheable(base_url.as_ref()));
+        self.query_with_cache(method, base_url, cache)
+    }
+
+    /// Returns whether the response to the URL may be cached, which is only the case for the
+    /// users, tracks and playlists of the API and their collections. The cache is not keyed by
+    /// account, so responses to a client that is logged in are never cached: they may contain
+    /// private tracks and the collections of the account. Searches and resolved URLs are not
+    /// cached either. Responses that turn out to include anything that is not public are not
+    /// stored, see is_public_body.
+    fn is_cacheable(&self, url: &str) -> bool {
+        if self.token.is_some() {
+            return false;
+        }
+        let path = match url.strip_prefix(&self.urls.api) {
+            Some(path) => path.split('?').next().unwrap_or(""),
+            None => return false,
+        };
+        let mut segments = path.trim_start_matches('/').split('/');
+        match (segments.next(), segments.next()) {
+            (Some("users"), Some(id))
+            | (Some("tracks"), Some(id))
+            | (Some("playlists"), Some(id)) => !id.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Like query, but always goes to the network. This should be used for responses that are
+    /// short-lived, like signed URLs.
+    pub(crate) fn query_uncached<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        base_url: impl AsRef<str>,
+    ) -> Result<T, Error> {
+        self.query_with_cache(method, base_url, None)
+    }
+
+    fn query_with_cache<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        base_url: impl AsRef<str>,
+        cache: Option<&DiskCache>,
     ) -> Result<T, Error> {
         let (req, url) = self.request(method.clone(), base_url)?;
+
+        if let Some(body) = cache.and_then(|c| c.get(&url)) {
+            match serde_json::from_str(&body) {
+                Ok(t) => return Ok(t),
+                Err(err) => warn!("bad cached body for {}: {}", url, err),
+            }
+        }
+
         info!("querying {} {}", method, url);
         let mut buf = Vec::new();
         retry_execute(&self.client, req.build()?)?
@@ -163,7 +438,12 @@ impl Client {
             .copy_to(&mut buf)?;
 
         match serde_json::from_slice(&buf[..]) {
-            Ok(t) => Ok(t),
+            Ok(t) => {
+                if let Some(cache) = cache.filter(|_| is_public_body(&buf)) {
+                    cache.put(&url, &String::from_utf8_lossy(&buf[..]));
+                }
+                Ok(t)
+            }
             Err(err) => {
                 let body = String::from_utf8_lossy(&buf[..]);
                 warn!("bad body: {}", body);
@@ -179,6 +459,26 @@ impl Client {
     }
 }
 
+/// Returns whether every track and playlist in the JSON body has public sharing, so it can be
+/// stored in a cache that is shared by all clients.
+fn is_public_body(body: &[u8]) -> bool {
+    fn public(value: &serde_json::Value) -> bool {
+        match value {
+            serde_json::Value::Object(object) => {
+                object.iter().all(|(key, value)| match key.as_str() {
+                    "sharing" => value == "public",
+                    _ => public(value),
+                })
+            }
+            serde_json::Value::Array(values) => values.iter().all(public),
+            _ => true,
+        }
+    }
+    serde_json::from_slice(body)
+        .map(|value| public(&value))
+        .unwrap_or(false)
+}
+
 impl fmt::Debug for Client {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let token = self
@@ -191,7 +491,18 @@ impl fmt::Debug for Client {
     }
 }
 
-fn anonymous_client_id(client: &blocking::Client) -> Result<String, Error> {
+/// Limits the rate and concurrency of all requests to SoundCloud. A value of 0 disables the
+/// respective limit.
+pub fn set_rate_limit(requests_per_second: f64, max_concurrent: usize) {
+    util::ratelimit::LIMITER.configure(requests_per_second, max_concurrent);
+}
+
+/// Returns how well read-ahead has worked out for all audio streams that have been closed.
+pub fn prefetch_stats() -> PrefetchStats {
+    http::prefetch_totals()
+}
+
+fn anonymous_client_id(client: &blocking::Client, urls: &BaseUrls) -> Result<String, Error> {
     lazy_static! {
         static ref RE_SCRIPT_TAG: Regex =
             Regex::new("<script crossorigin src=\"(.+)\"></script>").unwrap();
@@ -200,9 +511,9 @@ fn anonymous_client_id(client: &blocking::Client) -> Result<String, Error> {
 
     // Find the last <script> on the main page.
     let main_page_html = {
-        let url = "https://soundcloud.com/discover";
+        let url = format!("{}/discover", urls.web);
         info!("querying GET {}", url);
-        let mut resp = retry_execute(client, client.get(url).build()?)?.error_for_status()?;
+        let mut resp = retry_execute(client, client.get(&url).build()?)?.error_for_status()?;
         let mut buf = Vec::new();
         resp.copy_to(&mut buf)?;
         buf
@@ -254,9 +565,16 @@ struct Session {
     access_token: String,
 }
 
+#[derive(Deserialize)]
+struct Play {
+    track: Track,
+}
+
 #[derive(Deserialize)]
 struct Page<T> {
     collection: Vec<T>,
+    #[serde(default)]
+    next_href: Option<String>,
 }
 
 impl<T: DeserializeOwned + Send> Page<T> {
@@ -289,14 +607,144 @@ impl<T: DeserializeOwned + Send> Page<T> {
             .collect();
         Ok(all)
     }
+
+    /// Fetches all pages of a collection of which the size is not known in advance by following
+    /// the links to the next page. Unlike all_with_size_hint, the pages are fetched one by one.
+    /// No more pages are fetched once `limit` items have been fetched.
+    fn all_linked(
+        client: &Client,
+        base_url: impl AsRef<str>,
+        limit: usize,
+    ) -> Result<Vec<T>, Error> {
+        let mut url = Url::parse_with_params(
+            base_url.as_ref(),
+            &[
+                ("linked_partitioning", "1"),
+                ("limit", &format!("{}", PAGE_MAX_SIZE.min(limit as u64))),
+            ],
+        )?;
+        let mut all = Vec::new();
+        loop {
+            let page: Page<T> = client.query(Method::GET, url)?;
+            let empty = page.collection.is_empty();
+            all.extend(page.collection);
+            url = match page.next_href {
+                Some(next) if !empty && all.len() < limit => Url::parse(&next)?,
+                _ => break,
+            };
+        }
+        all.truncate(limit);
+        Ok(all)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use super::mock::MockServer;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+
     #[test]
     fn anonymous_client() {
-        Client::anonymous().unwrap();
+        let server = MockServer::start();
+        let client = Client::anonymous_with_urls(server.urls()).unwrap();
+        assert_eq!("mock-client-id", client.client_id);
+    }
+
+    #[test]
+    fn cache_public_only() {
+        let server = MockServer::start();
+        let dir = env::temp_dir().join(format!("soundcloud-fs-client-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = DiskCache::new(&dir, Duration::from_secs(60)).unwrap();
+        let client = server.client().with_cache(cache.clone());
+        let logged_in = server.login("alice").with_cache(cache);
+        for _ in 0..2 {
+            User::by_name(&client, "bob").unwrap();
+            Track::by_id(&client, 101).unwrap();
+            // The secret song is private.
+            Track::by_id(&client, 104).unwrap();
+            client.search_tracks("song", 10).unwrap();
+            client.resolve("alice/first-song").unwrap();
+            User::by_name(&logged_in, "carol").unwrap();
+            logged_in.me().unwrap();
+        }
+        let requests = |prefix: &str| {
+            server
+                .requests()
+                .into_iter()
+                .filter(|r| r.starts_with(prefix))
+                .count()
+        };
+        assert_eq!(1, requests("/users/bob?"));
+        assert_eq!(1, requests("/tracks/101?"));
+        assert_eq!(2, requests("/tracks/104?"));
+        assert_eq!(2, requests("/tracks?"));
+        assert_eq!(2, requests("/resolve?"));
+        assert_eq!(2, requests("/users/carol?"));
+        assert_eq!(2, requests("/me?"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn public_body() {
+        assert!(is_public_body(br#"{"id": 1, "sharing": "public"}"#));
+        assert!(is_public_body(br#"{"collection": [{"user": {"id": 1}}]}"#));
+        assert!(!is_public_body(
+            br#"{"collection": [{"sharing": "public"}, {"sharing": "private"}]}"#
+        ));
+    }
+
+    #[test]
+    fn resolve() {
+        let server = MockServer::start();
+        let client = server.client();
+        match client.resolve(&server.url("/alice/first-song")).unwrap() {
+            Resource::Track(track) => assert_eq!(101, track.id),
+            r => panic!("unexpected resource: {:?}", r),
+        }
+        match client.resolve("alice/sets/mixtape").unwrap() {
+            Resource::Playlist(playlist) => assert_eq!(301, playlist.id),
+            r => panic!("unexpected resource: {:?}", r),
+        }
+        match client.resolve("/bob").unwrap() {
+            Resource::User(user) => assert_eq!(2, user.id),
+            r => panic!("unexpected resource: {:?}", r),
+        }
+        assert!(client.resolve("alice/nope").is_err());
+    }
+
+    #[test]
+    fn paginated_collection() {
+        let server = MockServer::start();
+        let client = server.client();
+        let user = User::by_name(&client, "bob").unwrap();
+        let tracks = user.tracks(&client).unwrap();
+        assert_eq!(user.track_count as usize, tracks.len());
+        assert!(tracks.len() as u64 > PAGE_MAX_SIZE);
+        let page_requests = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.starts_with("/users/2/tracks?"))
+            .count();
+        assert_eq!(2, page_requests);
+    }
+
+    #[test]
+    fn linked_collection() {
+        let server = MockServer::start();
+        let client = server.client();
+        let user = User::by_name(&client, "bob").unwrap();
+        let url = client.api_url(format!("/users/{}/tracks", user.id));
+        let tracks: Vec<Track> = Page::all_linked(&client, &url, usize::MAX).unwrap();
+        assert_eq!(user.track_count as usize, tracks.len());
+        assert_eq!(201, tracks[0].id);
+        assert_eq!(450, tracks[249].id);
+
+        let tracks: Vec<Track> = Page::all_linked(&client, &url, 10).unwrap();
+        assert_eq!(10, tracks.len());
     }
 }
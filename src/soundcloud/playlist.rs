@@ -0,0 +1,63 @@
+use super::{format, Track, TrackUser};
+use chrono::{DateTime, Utc};
+use std::hash::{Hash, Hasher};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Playlist {
+    /// Integer ID
+    pub id: i64,
+    /// Creation timestamp, e.g. "2010/11/02 09:24:50 +0000"
+    #[serde(with = "format::date")]
+    pub created_at: DateTime<Utc>,
+    /// Last modified timestamp, e.g. "2017/09/24 09:15:49 +0000"
+    #[serde(with = "format::date")]
+    pub last_modified: DateTime<Utc>,
+    /// ID of the user that owns the playlist
+    pub user_id: i64,
+    /// Total duration of all tracks in milliseconds
+    #[serde(rename = "duration")]
+    pub duration_ms: i64,
+    /// Sharing status, e.g. "public"
+    pub sharing: String,
+    /// Space separated list of tags
+    pub tag_list: String,
+    /// Permalink of the resource, e.g. "field-recordings"
+    pub permalink: String,
+    /// Number of tracks in the playlist
+    pub track_count: i64,
+    /// Title, e.g. "Field Recordings"
+    pub title: String,
+    /// Description, e.g. "A collection of recordings made on the street"
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub description: Option<String>,
+    /// Genre, e.g. "Electronic"
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub genre: Option<String>,
+    /// Kind of playlist, e.g. "album", "ep single"
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub playlist_type: Option<String>,
+    /// Label name, e.g. "BeatLabel"
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub label_name: Option<String>,
+    /// Year of release
+    pub release_year: Option<i32>,
+    /// License, e.g. "all-rights-reserved"
+    pub license: String,
+    /// API resource URL, e.g. "http://api.soundcloud.com/playlists/405726"
+    pub uri: String,
+    /// URL to the SoundCloud.com page, e.g. "http://soundcloud.com/bryan/sets/field-recordings"
+    pub permalink_url: String,
+    /// URL to a JPEG image
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub artwork_url: Option<String>,
+    /// Mini representation of the owner
+    pub user: TrackUser,
+    /// The tracks in the playlist, in playlist order
+    pub tracks: Vec<Track>,
+}
+
+impl Hash for Playlist {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
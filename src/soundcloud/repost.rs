@@ -0,0 +1,25 @@
+use super::{format, Playlist, Track};
+use chrono::{DateTime, Utc};
+
+/// An item of the reposts of a user, which may be either a track or a playlist.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum Repost {
+    #[serde(rename = "track-repost")]
+    Track {
+        /// Timestamp of the repost, e.g. "2017/09/24 09:15:49 +0000"
+        #[serde(with = "format::date")]
+        created_at: DateTime<Utc>,
+        track: Box<Track>,
+    },
+    #[serde(rename = "playlist-repost")]
+    Playlist {
+        /// Timestamp of the repost, e.g. "2017/09/24 09:15:49 +0000"
+        #[serde(with = "format::date")]
+        created_at: DateTime<Utc>,
+        playlist: Box<Playlist>,
+    },
+    /// Kinds of items that are not supported, which are skipped.
+    #[serde(other)]
+    Other,
+}
@@ -0,0 +1,13 @@
+use super::{Playlist, Track, User};
+
+/// The object a URL on the SoundCloud website refers to.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Resource {
+    #[serde(rename = "track")]
+    Track(Box<Track>),
+    #[serde(rename = "user")]
+    User(Box<User>),
+    #[serde(rename = "playlist")]
+    Playlist(Box<Playlist>),
+}
@@ -6,7 +6,7 @@ use reqwest::Method;
 use std::hash::{Hash, Hasher};
 use std::io;
 
-const AUDIO_CBR_BITRATE: u64 = 128_000;
+pub(crate) const AUDIO_CBR_BITRATE: u64 = 128_000;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Track {
@@ -81,24 +81,20 @@ pub struct Track {
 }
 
 impl Track {
-    #[cfg(test)]
     pub fn by_id(client: &Client, id: i64) -> Result<Self, Error> {
-        let url = format!("https://api.soundcloud.com/tracks/{}", id);
+        let url = client.api_url(format!("/tracks/{}", id));
         client.query(Method::GET, url)
     }
 
     pub fn by_permalink(client: &Client, user_pl: &str, track_pl: &str) -> Result<Self, Error> {
-        let url = format!(
-            "https://api.soundcloud.com/users/{}/tracks/{}",
-            user_pl, track_pl,
-        );
+        let url = client.api_url(format!("/users/{}/tracks/{}", user_pl, track_pl));
         client.query(Method::GET, url)
     }
 
-    pub fn audio<'a>(&self, client: &'a Client) -> Result<impl io::Read + io::Seek + 'a, Error> {
+    pub fn audio(&self, client: &Client) -> Result<impl io::Read + io::Seek + Send, Error> {
         lazy_static! {
             static ref RE_HLS_URL: regex::Regex =
-                regex::Regex::new("https://[^\"]+?/stream/hls").unwrap();
+                regex::Regex::new("https?://[^\"]+?/stream/hls").unwrap();
             static ref RE_MP3_URL: regex::Regex =
                 regex::Regex::new("^(.+/media)/(\\d+)/(\\d+)/(.+)$").unwrap();
         }
@@ -109,7 +105,7 @@ impl Track {
             .map(|m| m.as_str())
             .ok_or_else(|| Error::Generic("hls url not found on page".to_string()))?;
         // Query the URL, the returned object contains another URL which points to a playlist file.
-        let hls_info: HLSInfo = client.query(Method::GET, hls_url)?;
+        let hls_info: HLSInfo = client.query_uncached(Method::GET, hls_url)?;
         // Get the playlist file.
         let playlist_file = retry_execute(
             default_client(),
@@ -133,7 +129,9 @@ impl Track {
             .ok_or_else(|| Error::Generic("unexpected MP3 url format".to_string()))?;
         let mp3_url = format!("{}/{}/{}/{}", &cap[1], 0, &cap[3], &cap[4]);
         let req = default_client().get(&mp3_url).build()?;
-        Ok(http::RangeSeeker::new(default_client(), req))
+        Ok(http::RangeSeeker::new(default_client(), req)
+            .with_read_ahead(client.read_ahead)
+            .with_resume_retries(client.resume_retries))
     }
 
     pub fn audio_size(&self) -> u64 {
@@ -197,17 +195,26 @@ mod tests {
     use super::*;
     use io::Read;
 
+    use crate::soundcloud::mock::MockServer;
+
     #[test]
     fn get_audio() {
-        // https://soundcloud.com/wright-and-bastard/the-fat-dandy-butterfly-slims
-        // CC BY-NC-SA 3.0
-        let id = 609233313;
-
-        let client = Client::anonymous().unwrap();
-        let track = Track::by_id(&client, id).unwrap();
+        let server = MockServer::start();
+        let client = server.client();
+        let track = Track::by_id(&client, 101).unwrap();
 
         let mut r = track.audio(&client).unwrap();
-        let mut b = [0; 4096];
-        r.read_exact(&mut b[..]).unwrap();
+        let mut b = Vec::new();
+        r.read_to_end(&mut b).unwrap();
+        assert_eq!(MockServer::audio(101), b);
+    }
+
+    #[test]
+    fn by_permalink() {
+        let server = MockServer::start();
+        let client = server.client();
+        let track = Track::by_permalink(&client, "alice", "first-song").unwrap();
+        assert_eq!(101, track.id);
+        assert!(Track::by_permalink(&client, "alice", "nope").is_err());
     }
 }
@@ -1,4 +1,4 @@
-use super::{format, Client, Error, Page, Track};
+use super::{format, Client, Error, Page, Playlist, Repost, Track};
 use chrono::{DateTime, Utc};
 use reqwest::Method;
 use std::hash::{Hash, Hasher};
@@ -62,24 +62,57 @@ impl User {
     pub fn by_name(client: &Client, name: impl AsRef<str>) -> Result<User, Error> {
         client.query(
             Method::GET,
-            format!("https://api.soundcloud.com/users/{}", name.as_ref()),
+            client.api_url(format!("/users/{}", name.as_ref())),
         )
     }
 
     pub fn tracks(&self, client: &Client) -> Result<Vec<Track>, Error> {
-        let url = format!("https://api.soundcloud.com/users/{}/tracks", self.id);
+        let url = client.api_url(format!("/users/{}/tracks", self.id));
         Page::all_with_size_hint(client, url, self.track_count as u64)
     }
 
     pub fn favorites(&self, client: &Client) -> Result<Vec<Track>, Error> {
-        let url = format!("https://api.soundcloud.com/users/{}/favorites", self.id);
+        let url = client.api_url(format!("/users/{}/favorites", self.id));
         Page::all_with_size_hint(client, url, self.public_favorites_count as u64)
     }
 
+    /// Returns the tracks and playlists reposted by the user, most recent first. Kinds of items
+    /// that are not supported are left out.
+    pub fn reposts(&self, client: &Client) -> Result<Vec<Repost>, Error> {
+        let url = client.api_url(format!("/users/{}/reposts", self.id));
+        let reposts: Vec<Repost> = Page::all_linked(client, url, usize::MAX)?;
+        Ok(reposts
+            .into_iter()
+            .filter(|repost| !matches!(repost, Repost::Other))
+            .collect())
+    }
+
     pub fn following(&self, client: &Client) -> Result<Vec<User>, Error> {
-        let url = format!("https://api.soundcloud.com/users/{}/followings", self.id);
+        let url = client.api_url(format!("/users/{}/followings", self.id));
         Page::all_with_size_hint(client, url, self.followings_count as u64)
     }
+
+    /// Returns the users that follow this user. Popular users may have a lot of followers, so
+    /// this can take many requests.
+    pub fn followers(&self, client: &Client) -> Result<Vec<User>, Error> {
+        let url = client.api_url(format!("/users/{}/followers", self.id));
+        Page::all_with_size_hint(client, url, self.followers_count as u64)
+    }
+
+    /// Makes the user the client is logged in as follow this user.
+    pub fn follow(&self, client: &Client) -> Result<(), Error> {
+        client.update_collection(Method::PUT, "followings", self.id)
+    }
+
+    /// Makes the user the client is logged in as stop following this user.
+    pub fn unfollow(&self, client: &Client) -> Result<(), Error> {
+        client.update_collection(Method::DELETE, "followings", self.id)
+    }
+
+    pub fn playlists(&self, client: &Client) -> Result<Vec<Playlist>, Error> {
+        let url = client.api_url(format!("/users/{}/playlists", self.id));
+        Page::all_with_size_hint(client, url, self.playlist_count as u64)
+    }
 }
 
 impl Hash for User {
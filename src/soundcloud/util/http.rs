@@ -1,15 +1,33 @@
+use super::ratelimit::LIMITER;
+use chrono::{DateTime, Utc};
 use log::*;
 use reqwest::blocking::{Client, Request, Response};
 use reqwest::header::{self, HeaderValue};
 use reqwest::StatusCode;
-use std::io;
+use std::io::{self, Read};
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
+const PREFETCH_CHUNK_SIZE: usize = 16 * 1024;
+
+pub const DEFAULT_RESUME_RETRIES: u32 = 5;
+
+// Retry-After values beyond this are clamped so filesystem operations do not hang indefinitely.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Executes a request, retrying it on connection errors and when the server is overloaded.
+///
+/// The request only counts towards the concurrency limit of the LIMITER until the headers of the
+/// response have been received. The body is read afterwards, which for audio takes as long as the
+/// file is open, so holding on to the permit would block all other requests.
 pub fn retry_execute(client: &Client, request: Request) -> reqwest::Result<Response> {
     let mut err = None;
-    for attempt in 0..5 {
+    for attempt in 0..MAX_ATTEMPTS {
         if let Some(err) = &err {
             warn!("query {}: {:?}, retrying", request.url(), err);
         }
@@ -17,9 +35,25 @@ pub fn retry_execute(client: &Client, request: Request) -> reqwest::Result<Respo
         let req = request
             .try_clone()
             .expect("retry_execute requires a clone-able request");
-        err = match client.execute(req) {
-            Ok(res) if res.status().is_server_error() => {
-                thread::sleep(Duration::from_millis(100) * (1 << attempt));
+        let rs = {
+            let _permit = LIMITER.acquire();
+            client.execute(req)
+        };
+        err = match rs {
+            Ok(res)
+                if res.status() == StatusCode::TOO_MANY_REQUESTS
+                    || res.status().is_server_error() =>
+            {
+                if attempt + 1 < MAX_ATTEMPTS {
+                    let delay = retry_after(&res).map(|delay| delay.min(MAX_RETRY_AFTER));
+                    let backoff = Duration::from_millis(100) * (1 << attempt);
+                    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                        // Being rate limited affects all requests, so everyone should back off.
+                        LIMITER.pause(delay.unwrap_or(backoff));
+                    } else {
+                        thread::sleep(delay.unwrap_or(backoff));
+                    }
+                }
                 Some(res.error_for_status().unwrap_err())
             }
             Ok(res) => return Ok(res),
@@ -29,9 +63,160 @@ pub fn retry_execute(client: &Client, request: Request) -> reqwest::Result<Respo
     Err(err.expect("retry loop should run at least once"))
 }
 
+/// Parses the Retry-After header, which is either a number of seconds or a date.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&Utc) - now).to_std().ok()
+}
+
+/// Counters that describe the effectiveness of read-ahead.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrefetchStats {
+    /// Reads that were served from data that had already been fetched.
+    pub hits: u64,
+    /// Reads that had to wait for data from the network.
+    pub misses: u64,
+    /// Seeks that threw away the data that was fetched ahead, either by going back or by going
+    /// further ahead than had been fetched.
+    pub discards: u64,
+}
+
+/// The counters of all RangeSeekers that have been dropped.
+static PREFETCH_TOTALS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+/// Returns the sum of the read-ahead counters of all readers that have been closed.
+pub fn prefetch_totals() -> PrefetchStats {
+    PrefetchStats {
+        hits: PREFETCH_TOTALS[0].load(Ordering::Relaxed),
+        misses: PREFETCH_TOTALS[1].load(Ordering::Relaxed),
+        discards: PREFETCH_TOTALS[2].load(Ordering::Relaxed),
+    }
+}
+
+/// Prefetch reads from a response on a separate thread to keep a number of bytes ahead of the
+/// reader.
+struct Prefetch {
+    // An empty chunk signals the end of the body.
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl Prefetch {
+    fn new(mut inner: impl io::Read + Send + 'static, window: u64) -> Self {
+        let capacity = (window as usize / PREFETCH_CHUNK_SIZE).max(1);
+        let (sender, chunks) = mpsc::sync_channel(capacity);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; PREFETCH_CHUNK_SIZE];
+            let rs = inner.read(&mut chunk).map(|n| {
+                chunk.truncate(n);
+                chunk
+            });
+            let last = rs.as_ref().map(|c| c.is_empty()).unwrap_or(true);
+            // Sending fails if the reading end has been dropped.
+            if sender.send(rs).is_err() || last {
+                break;
+            }
+        });
+        Prefetch {
+            chunks,
+            current: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8], stats: &mut PrefetchStats) -> io::Result<usize> {
+        if self.pos < self.current.len() {
+            stats.hits += 1;
+        } else {
+            if self.eof {
+                return Ok(0);
+            }
+            let chunk = match self.chunks.try_recv() {
+                Ok(v) => {
+                    stats.hits += 1;
+                    v
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    stats.misses += 1;
+                    self.chunks.recv().map_err(|_| prefetch_stopped())?
+                }
+                Err(mpsc::TryRecvError::Disconnected) => return Err(prefetch_stopped()),
+            }?;
+            if chunk.is_empty() {
+                self.eof = true;
+                return Ok(0);
+            }
+            self.current = chunk;
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+    /// Attempts to move ahead by the specified number of bytes using only data that has already
+    /// been fetched. If this is not possible, false is returned and the prefetcher should be
+    /// discarded.
+    fn skip(&mut self, mut n: u64) -> bool {
+        loop {
+            let available = (self.current.len() - self.pos) as u64;
+            if n <= available {
+                self.pos += n as usize;
+                return true;
+            }
+            n -= available;
+            match self.chunks.try_recv() {
+                Ok(Ok(chunk)) if !chunk.is_empty() => {
+                    self.current = chunk;
+                    self.pos = 0;
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+fn prefetch_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "read-ahead stopped after an error")
+}
+
+enum Body {
+    Direct(Box<Response>),
+    Prefetch(Prefetch),
+}
+
+impl Body {
+    fn new(res: Response, read_ahead: u64) -> Self {
+        if read_ahead == 0 {
+            Body::Direct(Box::new(res))
+        } else {
+            Body::Prefetch(Prefetch::new(res, read_ahead))
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8], stats: &mut PrefetchStats) -> io::Result<usize> {
+        match self {
+            Body::Direct(res) => res.read(buf),
+            Body::Prefetch(p) => p.read(buf, stats),
+        }
+    }
+}
+
 enum State {
     NoResponse,
-    Response(Box<Response>),
+    Response(Body),
     OutOfRange,
 }
 
@@ -45,7 +230,14 @@ pub struct RangeSeeker<'a> {
     content_length: Option<u64>,
 
     // The previous request scheme is used as an optimization for file size probes.
-    response_cache: Option<(Box<Response>, u64)>,
+    response_cache: Option<(Body, u64)>,
+
+    // The number of bytes to fetch ahead of the reader, 0 disables read-ahead.
+    read_ahead: u64,
+    prefetch_stats: PrefetchStats,
+
+    // The number of times a response that was cut off is resumed before an error is returned.
+    resume_retries: u32,
 }
 
 impl<'a> RangeSeeker<'a> {
@@ -60,9 +252,32 @@ impl<'a> RangeSeeker<'a> {
             current_offset: 0,
             content_length: None,
             response_cache: None,
+            read_ahead: 0,
+            prefetch_stats: PrefetchStats::default(),
+            resume_retries: DEFAULT_RESUME_RETRIES,
         }
     }
 
+    /// Enables fetching the specified number of bytes ahead of the current offset in the
+    /// background.
+    pub fn with_read_ahead(mut self, bytes: u64) -> Self {
+        self.read_ahead = bytes;
+        self
+    }
+
+    /// Sets the number of consecutive times a response body that ends early or fails is resumed
+    /// at the current offset before the error is returned to the reader.
+    pub fn with_resume_retries(mut self, retries: u32) -> Self {
+        self.resume_retries = retries;
+        self
+    }
+
+    /// Returns how well read-ahead has worked out so far. The counters stay at zero if read-ahead
+    /// is disabled.
+    pub fn prefetch_stats(&self) -> PrefetchStats {
+        self.prefetch_stats
+    }
+
     fn next_resp(&mut self) -> io::Result<()> {
         let mut req = self.req.try_clone().unwrap();
         req.headers_mut().insert(
@@ -104,7 +319,7 @@ impl<'a> RangeSeeker<'a> {
                 )
             })?;
             self.content_length = Some(self.current_offset + clen);
-            self.state = State::Response(Box::new(res));
+            self.state = State::Response(Body::new(res, self.read_ahead));
             return Ok(());
         }
 
@@ -130,24 +345,56 @@ impl<'a> io::Read for RangeSeeker<'a> {
             }
         }
 
-        if let State::NoResponse = self.state {
-            self.next_resp()?;
-        }
-        let res = match self.state {
-            State::Response(ref mut res) => res,
-            State::OutOfRange => {
-                return Ok(0);
+        let mut nread = 0;
+        let mut retries = 0;
+        while nread < buf.len() {
+            if let State::NoResponse = self.state {
+                self.next_resp()?;
             }
-            _ => unreachable!(),
-        };
+            let res = match self.state {
+                State::Response(ref mut res) => res,
+                State::OutOfRange => break,
+                _ => unreachable!(),
+            };
 
-        let mut nread = 0;
-        let mut n = 1;
-        while !buf.is_empty() && n > 0 {
-            n = res.read(&mut buf[nread..])?;
-            nread += n;
+            let err = match res.read(&mut buf[nread..], &mut self.prefetch_stats) {
+                Ok(0) => {
+                    let truncated = self
+                        .content_length
+                        .map(|l| self.current_offset < l)
+                        .unwrap_or(false);
+                    if !truncated {
+                        break;
+                    }
+                    io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response body ended before Content-Length was reached",
+                    )
+                }
+                Ok(n) => {
+                    nread += n;
+                    self.current_offset += n as u64;
+                    retries = 0;
+                    continue;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => err,
+            };
+
+            // The body was cut off, reissue the request from where we are now.
+            if retries >= self.resume_retries {
+                self.state = State::NoResponse;
+                return Err(err);
+            }
+            retries += 1;
+            warn!(
+                "reading {}: {}, resuming at offset {}",
+                self.req.url(),
+                err,
+                self.current_offset
+            );
+            self.state = State::NoResponse;
         }
-        self.current_offset += nread as u64;
         Ok(nread)
     }
 }
@@ -174,6 +421,19 @@ impl<'a> io::Seek for RangeSeeker<'a> {
             State::NoResponse
         };
 
+        if abs_offset > self.current_offset && pos != io::SeekFrom::End(0) {
+            // Try to skip ahead using data that has already been fetched.
+            if let State::Response(Body::Prefetch(p)) = &mut self.state {
+                if p.skip(abs_offset - self.current_offset) {
+                    self.current_offset = abs_offset;
+                    return Ok(abs_offset);
+                }
+                // The prefetcher has consumed some of the data, so it can not be reused.
+                self.prefetch_stats.discards += 1;
+                self.state = State::NoResponse;
+            }
+        }
+
         if self.current_offset != abs_offset {
             // Get the previous state. This also rewrites the old state to new state so the next
             // operation will trigger a HTTP request if needed.
@@ -183,6 +443,10 @@ impl<'a> io::Seek for RangeSeeker<'a> {
                 State::Response(res) => Some(res),
                 _ => None,
             };
+            if let Some(Body::Prefetch(_)) = previous_response {
+                // Data that was fetched ahead of the old offset is of no use at the new one.
+                self.prefetch_stats.discards += 1;
+            }
             // If we have a cached response that has the same absolute offset as desired, reuse it.
             if self.response_cache.as_ref().map(|(_, o)| *o) == Some(abs_offset) {
                 let (cached_response, _) = self.response_cache.take().unwrap();
@@ -198,6 +462,18 @@ impl<'a> io::Seek for RangeSeeker<'a> {
     }
 }
 
+impl<'a> Drop for RangeSeeker<'a> {
+    fn drop(&mut self) {
+        if self.read_ahead > 0 {
+            let stats = self.prefetch_stats();
+            debug!("read-ahead for {}: {:?}", self.req.url(), stats);
+            PREFETCH_TOTALS[0].fetch_add(stats.hits, Ordering::Relaxed);
+            PREFETCH_TOTALS[1].fetch_add(stats.misses, Ordering::Relaxed);
+            PREFETCH_TOTALS[2].fetch_add(stats.discards, Ordering::Relaxed);
+        }
+    }
+}
+
 fn content_length(res: &Response) -> Option<u64> {
     res.headers()
         .get(header::CONTENT_LENGTH)
@@ -218,21 +494,14 @@ fn valid_offset(offset: i64) -> Result<u64, io::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::soundcloud::mock::MockServer;
     use std::io::{Read, Seek};
 
-    fn test_request(size: usize) -> Request {
-        // Ideally, we are not dependant on an external server for unit tests...
-        let mut req = Request::new(
+    fn test_request(server: &MockServer, size: usize) -> Request {
+        Request::new(
             reqwest::Method::GET,
-            format!("https://httpbin.org/range/{}", size)
-                .parse()
-                .unwrap(),
-        );
-        req.headers_mut().insert(
-            header::ACCEPT,
-            HeaderValue::from_static("application/octet-stream"),
-        );
-        req
+            server.url(&format!("/range/{}", size)).parse().unwrap(),
+        )
     }
 
     fn test_request_resp(start: usize, end: usize) -> Vec<u8> {
@@ -244,11 +513,172 @@ mod tests {
             .collect()
     }
 
+    /// Serves `data` over HTTP, but cuts the response body off after the number of bytes returned
+    /// by `body_len` for the requested offset.
+    fn flaky_server(data: Vec<u8>, body_len: fn(usize) -> usize) -> Request {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut offset = 0;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    let line = line.to_lowercase();
+                    if let Some(range) = line.strip_prefix("range: bytes=") {
+                        offset = range.trim().trim_end_matches('-').parse().unwrap();
+                    }
+                }
+                let body = &data[offset..];
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    body.len(),
+                    offset,
+                    data.len() - 1,
+                    data.len()
+                );
+                let _ = stream.write_all(&body[..body.len().min(body_len(offset))]);
+            }
+        });
+        Request::new(
+            reqwest::Method::GET,
+            format!("http://{}/", addr).parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_resume_truncated() {
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let client = Client::new();
+        let req = flaky_server(data.clone(), |_| 3000);
+
+        let mut f = RangeSeeker::new(&client, req);
+
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert_eq!(data, buf);
+        assert_eq!(4, f.num_requests);
+    }
+
+    #[test]
+    fn test_resume_budget() {
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let client = Client::new();
+        // Nothing past offset 5000 is ever sent.
+        let req = flaky_server(data, |offset| 5000usize.saturating_sub(offset));
+
+        let mut f = RangeSeeker::new(&client, req).with_resume_retries(2);
+
+        let mut buf = vec![0; 10_000];
+        assert!(f.read(&mut buf).is_err());
+        assert_eq!(5000, f.current_offset);
+        assert_eq!(3, f.num_requests);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            parse_retry_after("120", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now)
+        );
+        assert_eq!(
+            None,
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now)
+        );
+        assert_eq!(None, parse_retry_after("soon", now));
+    }
+
+    #[test]
+    fn test_prefetch_read() {
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let mut p = Prefetch::new(io::Cursor::new(data.clone()), 64 * 1024);
+        let mut stats = PrefetchStats::default();
+
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1000];
+        loop {
+            let n = p.read(&mut chunk, &mut stats).unwrap();
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(data, buf);
+        assert!(stats.hits > 0);
+    }
+
+    #[test]
+    fn test_prefetch_skip() {
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let mut p = Prefetch::new(io::Cursor::new(data.clone()), 64 * 1024);
+        let mut stats = PrefetchStats::default();
+
+        let mut buf = [0; 10];
+        p.read(&mut buf, &mut stats).unwrap();
+        // Wait until the first few chunks have been fetched.
+        thread::sleep(Duration::from_millis(100));
+        assert!(p.skip(30_000));
+        p.read(&mut buf, &mut stats).unwrap();
+        assert_eq!(data[30_010..30_020], buf);
+        assert!(!p.skip(1_000_000));
+    }
+
+    #[test]
+    fn test_prefetch_stats() {
+        const SIZE: usize = 100_000;
+        let client = Client::new();
+        let server = MockServer::start();
+        let req = test_request(&server, SIZE);
+
+        let before = prefetch_totals();
+        let mut f = RangeSeeker::new(&client, req).with_read_ahead(64 * 1024);
+        let mut buf = [0; 10];
+        f.read_exact(&mut buf).unwrap();
+        // Wait until the first few chunks have been fetched.
+        thread::sleep(Duration::from_millis(100));
+        f.seek(io::SeekFrom::Start(30_000)).unwrap();
+        f.read_exact(&mut buf).unwrap();
+        assert_eq!(test_request_resp(30_000, 30_010), buf);
+        assert_eq!(0, f.prefetch_stats().discards);
+        assert_eq!(1, f.num_requests);
+
+        f.seek(io::SeekFrom::Start(20)).unwrap();
+        f.read_exact(&mut buf).unwrap();
+        assert_eq!(test_request_resp(20, 30), buf);
+        let stats = f.prefetch_stats();
+        assert_eq!(1, stats.discards);
+        assert!(stats.hits > 0);
+        assert_eq!(2, f.num_requests);
+
+        // The counters are added to the totals when the reader is closed. Other tests may add
+        // to them at the same time.
+        drop(f);
+        let totals = prefetch_totals();
+        assert!(totals.discards >= before.discards + stats.discards);
+        assert!(totals.hits >= before.hits + stats.hits);
+    }
+
     #[test]
     fn test_read_all() {
         const SIZE: usize = 8192;
         let client = Client::new();
-        let req = test_request(SIZE);
+        let server = MockServer::start();
+        let req = test_request(&server, SIZE);
 
         let mut f = RangeSeeker::new(&client, req);
 
@@ -263,7 +693,8 @@ mod tests {
     fn test_read_partial() {
         const SIZE: usize = 8192;
         let client = Client::new();
-        let req = test_request(SIZE);
+        let server = MockServer::start();
+        let req = test_request(&server, SIZE);
 
         let mut f = RangeSeeker::new(&client, req);
 
@@ -281,7 +712,8 @@ mod tests {
     fn test_seek_to_end() {
         const SIZE: usize = 8192;
         let client = Client::new();
-        let req = test_request(SIZE);
+        let server = MockServer::start();
+        let req = test_request(&server, SIZE);
 
         let mut f = RangeSeeker::new(&client, req);
 
@@ -299,7 +731,8 @@ mod tests {
     fn test_probe_size() {
         const SIZE: usize = 8192;
         let client = Client::new();
-        let req = test_request(SIZE);
+        let server = MockServer::start();
+        let req = test_request(&server, SIZE);
 
         let mut f = RangeSeeker::new(&client, req);
 
@@ -320,7 +753,8 @@ mod tests {
     fn test_read_after_seek() {
         const SIZE: usize = 8192;
         let client = Client::new();
-        let req = test_request(SIZE);
+        let server = MockServer::start();
+        let req = test_request(&server, SIZE);
 
         let mut f = RangeSeeker::new(&client, req);
 
@@ -0,0 +1,166 @@
+use lazy_static::lazy_static;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// The limiter that is shared by all requests to SoundCloud.
+    pub static ref LIMITER: RateLimiter = RateLimiter::new(0.0, 0);
+}
+
+/// RateLimiter is a token bucket that limits the number of requests per second in combination
+/// with a limit on the number of requests that may be in flight at the same time.
+pub struct RateLimiter {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+struct State {
+    // Requests per second, 0 disables the rate limit.
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+    // The maximum number of active requests, 0 disables the limit.
+    max_concurrent: usize,
+    active: usize,
+    // Set when the server told us to back off.
+    paused_until: Option<Instant>,
+}
+
+impl State {
+    fn refill(&mut self, now: Instant) {
+        // Allow bursts of up to one second worth of requests.
+        let burst = self.rate.max(1.0);
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(burst);
+        self.updated = now;
+    }
+}
+
+/// Permit marks a request as active until it is dropped.
+pub struct Permit<'a> {
+    limiter: &'a RateLimiter,
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        state.active -= 1;
+        self.limiter.cond.notify_all();
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, max_concurrent: usize) -> Self {
+        RateLimiter {
+            state: Mutex::new(State {
+                rate,
+                tokens: rate.max(1.0),
+                updated: Instant::now(),
+                max_concurrent,
+                active: 0,
+                paused_until: None,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Changes the limits. A value of 0 disables the respective limit.
+    pub fn configure(&self, rate: f64, max_concurrent: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.rate = rate;
+        state.max_concurrent = max_concurrent;
+        self.cond.notify_all();
+    }
+
+    /// Blocks until a request may be made. The request counts towards the concurrency limit until
+    /// the returned permit is dropped.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            if let Some(until) = state.paused_until {
+                if now < until {
+                    state = self.cond.wait_timeout(state, until - now).unwrap().0;
+                    continue;
+                }
+                state.paused_until = None;
+            }
+            if state.max_concurrent > 0 && state.active >= state.max_concurrent {
+                state = self.cond.wait(state).unwrap();
+                continue;
+            }
+            if state.rate > 0.0 {
+                state.refill(now);
+                if state.tokens < 1.0 {
+                    let wait = Duration::from_secs_f64((1.0 - state.tokens) / state.rate);
+                    state = self.cond.wait_timeout(state, wait).unwrap().0;
+                    continue;
+                }
+                state.tokens -= 1.0;
+            }
+            state.active += 1;
+            return Permit { limiter: self };
+        }
+    }
+
+    /// Holds back all requests for the specified duration.
+    pub fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + duration;
+        if state.paused_until.map(|t| t < until).unwrap_or(true) {
+            state.paused_until = Some(until);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn unlimited() {
+        let limiter = RateLimiter::new(0.0, 0);
+        let start = Instant::now();
+        let _permits: Vec<_> = (0..100).map(|_| limiter.acquire()).collect();
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn rate() {
+        let limiter = RateLimiter::new(20.0, 0);
+        let start = Instant::now();
+        // The first 20 requests are a burst, the other 5 should take 250ms.
+        for _ in 0..25 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn max_concurrent() {
+        let limiter = Arc::new(RateLimiter::new(0.0, 1));
+        let permit = limiter.acquire();
+
+        let l = Arc::clone(&limiter);
+        let start = Instant::now();
+        let handle = thread::spawn(move || {
+            l.acquire();
+            start.elapsed()
+        });
+        thread::sleep(Duration::from_millis(100));
+        drop(permit);
+        assert!(handle.join().unwrap() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn pause() {
+        let limiter = RateLimiter::new(0.0, 0);
+        limiter.pause(Duration::from_millis(100));
+        let start = Instant::now();
+        limiter.acquire();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
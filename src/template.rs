@@ -0,0 +1,459 @@
+//! Filename templates for tracks.
+//!
+//! A template is a filename with fields between braces that are substituted with the metadata of
+//! a track, e.g. `{artist} - {title} [{id}].mp3`. Braces themselves are written as `{{` and `}}`.
+//! The following fields are available:
+//!
+//! * `id`: the numeric id of the track
+//! * `title`: the title of the track
+//! * `permalink`: the name of the track in its URL
+//! * `artist`: the display name of the uploader
+//! * `user`: the permalink of the uploader
+//! * `genre`: the genre, empty if not set
+//! * `created_at`: the upload date, formatted as `%Y-%m-%d` unless another format is specified
+//!   after a colon, e.g. `{created_at:%Y}`
+//! * `index`: the position in the directory, zero-padded to the same width for all entries
+
+use crate::soundcloud;
+use chrono::format::{Item, StrftimeItems};
+use regex::Regex;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+/// The maximum length of a filename in bytes.
+const MAX_NAME_LEN: usize = 255;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A brace is not closed or not opened.
+    Unbalanced,
+    UnknownField(String),
+    BadDateFormat(String),
+    /// Templates can not contain path separators.
+    Separator,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Id,
+    Title,
+    Permalink,
+    Artist,
+    User,
+    Genre,
+    CreatedAt,
+    Index,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Field(Field, Option<String>),
+}
+
+/// The fields of a track that can be recovered from a filename.
+#[derive(Debug, Default, PartialEq)]
+pub struct Key {
+    pub id: Option<i64>,
+    pub user: Option<String>,
+    pub permalink: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<Part>,
+    // The part of the last literal from its last dot onwards, which is kept intact when the name
+    // is shortened or de-duplicated.
+    extension: String,
+    // Matches names rendered by this template to recover the fields that identify a track.
+    pattern: Regex,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Template, Error> {
+        if s.contains('/') || s.contains('\0') {
+            return Err(Error::Separator);
+        }
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err(Error::Unbalanced),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)));
+                    }
+                    parts.push(parse_field(&spec)?);
+                }
+                '}' => return Err(Error::Unbalanced),
+                c => literal.push(c),
+            }
+        }
+
+        let extension = match literal.rfind('.') {
+            Some(i) => literal.split_off(i),
+            None => String::new(),
+        };
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        let mut pattern = String::from("^");
+        let mut captured = Vec::new();
+        for part in &parts {
+            match part {
+                Part::Literal(s) => pattern.push_str(&regex::escape(s)),
+                Part::Field(field, _) if captured.contains(field) => pattern.push_str(".*?"),
+                Part::Field(field, _) => {
+                    captured.push(*field);
+                    pattern.push_str(match field {
+                        Field::Id => "(?P<id>[0-9]+)",
+                        Field::User => "(?P<user>.+?)",
+                        Field::Permalink => "(?P<permalink>.+?)",
+                        Field::Index => "[0-9]+",
+                        _ => ".*?",
+                    });
+                }
+            }
+        }
+        pattern.push_str(r"(?: \((?P<dedup>[0-9]+)\))?");
+        pattern.push_str(&regex::escape(&extension));
+        pattern.push('$');
+
+        Ok(Template {
+            parts,
+            extension,
+            pattern: Regex::new(&pattern).unwrap(),
+        })
+    }
+
+    /// Renders the name of a track. `index` is the position of the track starting at 1 and the
+    /// number of entries in the directory, if the directory is ordered.
+    pub fn render(&self, track: &soundcloud::Track, index: Option<(usize, usize)>) -> String {
+        let mut stem = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => stem.push_str(s),
+                Part::Field(field, format) => match field {
+                    Field::Id => stem.push_str(&track.id.to_string()),
+                    Field::Title => stem.push_str(&track.title),
+                    Field::Permalink => stem.push_str(&track.permalink),
+                    Field::Artist => stem.push_str(&track.user.username),
+                    Field::User => stem.push_str(&track.user.permalink),
+                    Field::Genre => stem.push_str(track.genre.as_deref().unwrap_or("")),
+                    Field::CreatedAt => {
+                        let format = format.as_deref().unwrap_or("%Y-%m-%d");
+                        stem.push_str(&track.created_at.format(format).to_string());
+                    }
+                    Field::Index => {
+                        let (i, n) = index.unwrap_or((0, 0));
+                        let width = n.to_string().len().max(2);
+                        stem.push_str(&format!("{:0width$}", i, width = width));
+                    }
+                },
+            }
+        }
+        let mut stem = sanitize(&stem);
+        if stem.is_empty() {
+            stem = track.id.to_string();
+        }
+        self.assemble(&stem, "")
+    }
+
+    /// Renders the names of all tracks in a directory. Tracks that would end up with the same
+    /// name have their id appended, so the names do not depend on the order of the tracks.
+    pub fn render_all<'a, I>(&self, tracks: I, ordered: bool) -> Vec<String>
+    where
+        I: ExactSizeIterator<Item = &'a soundcloud::Track>,
+    {
+        let n = tracks.len();
+        let tracks: Vec<_> = tracks.collect();
+        let mut names: Vec<_> = tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| self.render(track, if ordered { Some((i + 1, n)) } else { None }))
+            .collect();
+
+        let mut counts = HashMap::new();
+        for name in &names {
+            *counts.entry(name.clone()).or_insert(0) += 1;
+        }
+        for (name, track) in names.iter_mut().zip(&tracks) {
+            if counts[name.as_str()] > 1 {
+                *name = self.with_suffix(name, &format!(" ({})", track.id));
+            }
+        }
+
+        // The same track may be listed more than once, which makes the names above collide
+        // again. Those are numbered in order of appearance.
+        let mut seen = HashMap::new();
+        for name in names.iter_mut() {
+            let count = seen.entry(name.clone()).or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                *name = self.with_suffix(name, &format!(" ({})", count));
+            }
+        }
+        names
+    }
+
+    /// Recovers the fields identifying a track from a name rendered by this template. Because
+    /// names may have been altered to make them valid, the track that is found should be rendered
+    /// again to check that it has this name.
+    pub fn parse_name(&self, name: &str) -> Option<Key> {
+        let captures = self.pattern.captures(name)?;
+        let id = captures
+            .name("id")
+            .or_else(|| captures.name("dedup"))
+            .and_then(|m| m.as_str().parse().ok());
+        Some(Key {
+            id,
+            user: captures.name("user").map(|m| m.as_str().to_string()),
+            permalink: captures.name("permalink").map(|m| m.as_str().to_string()),
+        })
+    }
+
+    /// Checks whether a name refers to the track, taking de-duplication into account.
+    pub fn matches(&self, name: &str, track: &soundcloud::Track) -> bool {
+        let plain = self.render(track, None);
+        name == plain || name == self.with_suffix(&plain, &format!(" ({})", track.id))
+    }
+
+    /// Returns whether names can be resolved to tracks without listing the directory.
+    pub fn is_resolvable(&self) -> bool {
+        let identifies = |part: &Part| {
+            matches!(
+                part,
+                Part::Field(Field::Id, _) | Part::Field(Field::Permalink, _)
+            )
+        };
+        self.parts.iter().any(identifies) && !self.has_index()
+    }
+
+    /// Returns whether a name may have been shortened to fit the length limit, in which case it
+    /// can not be parsed.
+    pub fn is_shortened(&self, name: &str) -> bool {
+        // A stem is cut at a character boundary, which can leave up to 3 bytes unused.
+        name.len() > MAX_NAME_LEN - 4
+    }
+
+    fn has_index(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(Field::Index, _)))
+    }
+
+    fn with_suffix(&self, name: &str, suffix: &str) -> String {
+        let stem = &name[..name.len() - self.extension.len()];
+        self.assemble(stem, suffix)
+    }
+
+    /// Joins the parts of a name, shortening the stem to keep the name within the length limit.
+    fn assemble(&self, stem: &str, suffix: &str) -> String {
+        let max = MAX_NAME_LEN.saturating_sub(self.extension.len() + suffix.len());
+        let mut end = stem.len().min(max);
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}{}{}", &stem[..end], suffix, self.extension)
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = Error;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Template::parse(&s)
+    }
+}
+
+fn parse_field(spec: &str) -> Result<Part, Error> {
+    let mut split = spec.splitn(2, ':');
+    let name = split.next().unwrap();
+    let format = split.next().map(str::to_string);
+    let field = match name {
+        "id" => Field::Id,
+        "title" => Field::Title,
+        "permalink" => Field::Permalink,
+        "artist" => Field::Artist,
+        "user" => Field::User,
+        "genre" => Field::Genre,
+        "created_at" => Field::CreatedAt,
+        "index" => Field::Index,
+        _ => return Err(Error::UnknownField(spec.to_string())),
+    };
+    match (&field, &format) {
+        (Field::CreatedAt, Some(f)) if StrftimeItems::new(f).any(|item| item == Item::Error) => {
+            return Err(Error::BadDateFormat(f.clone()));
+        }
+        (Field::CreatedAt, _) => (),
+        (_, Some(_)) => return Err(Error::UnknownField(spec.to_string())),
+        _ => (),
+    }
+    Ok(Part::Field(field, format))
+}
+
+/// Makes a string safe to use as a filename by replacing path separators and control characters
+/// and preventing it from being hidden.
+fn sanitize(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| match c {
+            '/' => '_',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let s = s.trim();
+    match s.strip_prefix('.') {
+        Some(rest) => format!("_{}", rest),
+        None => s.to_string(),
+    }
+}
+
+/// The templates used for the names of tracks, per kind of directory.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filenames {
+    /// The tracks uploaded by a user.
+    pub tracks: Template,
+    pub favorites: Template,
+    /// The tracks reposted by a user.
+    pub reposts: Template,
+    /// The tracks of a playlist, which are ordered.
+    pub playlists: Template,
+}
+
+impl Default for Filenames {
+    fn default() -> Self {
+        Filenames {
+            tracks: Template::parse("{permalink}.mp3").unwrap(),
+            favorites: Template::parse("{user}_-_{permalink}.mp3").unwrap(),
+            reposts: Template::parse("{user}_-_{permalink}.mp3").unwrap(),
+            playlists: Template::parse("{index}_-_{user}_-_{permalink}.mp3").unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::soundcloud::mock;
+
+    fn track(id: i64, title: &str) -> soundcloud::Track {
+        let json = mock::track_json("http://localhost", 1, "alice", id, "song");
+        let mut track: soundcloud::Track = serde_json::from_value(json).unwrap();
+        track.title = title.to_string();
+        track.genre = None;
+        track
+    }
+
+    #[test]
+    fn parse() {
+        assert!(Template::parse("{artist} - {title} [{id}].mp3").is_ok());
+        assert!(Template::parse("{{{title}}}.mp3").is_ok());
+        assert!(Template::parse("{created_at:%Y}.mp3").is_ok());
+        assert!(Template::parse("{title.mp3").is_err());
+        assert!(Template::parse("title}.mp3").is_err());
+        assert!(Template::parse("{nope}.mp3").is_err());
+        assert!(Template::parse("{title:%Y}.mp3").is_err());
+        assert!(Template::parse("{created_at:%!}.mp3").is_err());
+        assert!(Template::parse("{user}/{title}.mp3").is_err());
+    }
+
+    #[test]
+    fn render() {
+        let track = track(101, "A/B: the \"song\"");
+        let template = Template::parse("{artist} - {title} [{id}].mp3").unwrap();
+        assert_eq!(
+            "alice - A_B: the \"song\" [101].mp3",
+            template.render(&track, None)
+        );
+        let template = Template::parse("{{{index}}} {created_at:%Y}.mp3").unwrap();
+        assert_eq!("{007} 2019.mp3", template.render(&track, Some((7, 120))));
+        let template = Template::parse("{genre}.mp3").unwrap();
+        assert_eq!("101.mp3", template.render(&track, None));
+        let template = Template::parse(".{title}").unwrap();
+        assert_eq!("_A_B: the \"song\"", template.render(&track, None));
+    }
+
+    #[test]
+    fn length_limit() {
+        let title = "é".repeat(300);
+        let template = Template::parse("{title}.mp3").unwrap();
+        let name = template.render(&track(101, &title), None);
+        assert!(name.len() <= MAX_NAME_LEN);
+        assert!(name.ends_with("é.mp3"));
+
+        let tracks = [track(101, &title), track(102, &title)];
+        let names = template.render_all(tracks.iter(), false);
+        assert!(names[0].ends_with(" (101).mp3"));
+        assert!(names.iter().all(|n| n.len() <= MAX_NAME_LEN));
+    }
+
+    #[test]
+    fn dedup() {
+        let template = Template::parse("{title}.mp3").unwrap();
+        let tracks = [track(1, "a"), track(2, "b"), track(3, "a"), track(1, "a")];
+        assert_eq!(
+            vec!["a (1).mp3", "b.mp3", "a (3).mp3", "a (1) (2).mp3"],
+            template.render_all(tracks.iter(), false)
+        );
+        let mut reversed = tracks[..3].to_vec();
+        reversed.reverse();
+        assert_eq!(
+            vec!["a (3).mp3", "b.mp3", "a (1).mp3"],
+            template.render_all(reversed.iter(), false)
+        );
+        assert!(template.matches("a (3).mp3", &tracks[2]));
+        assert!(!template.matches("a (1).mp3", &tracks[2]));
+    }
+
+    #[test]
+    fn parse_name() {
+        let template = Template::parse("{user}_-_{permalink}.mp3").unwrap();
+        assert_eq!(
+            Some(Key {
+                id: None,
+                user: Some("bob".to_string()),
+                permalink: Some("track-1".to_string()),
+            }),
+            template.parse_name("bob_-_track-1.mp3")
+        );
+        assert_eq!(None, template.parse_name("bob_-_track-1.ogg"));
+
+        let template = Template::parse("{title} [{id}].mp3").unwrap();
+        let key = template.parse_name("Some [thing] [42].mp3").unwrap();
+        assert_eq!(Some(42), key.id);
+        let template = Template::parse("{title}.mp3").unwrap();
+        assert_eq!(Some(7), template.parse_name("x (7).mp3").unwrap().id);
+        assert!(!template.is_resolvable());
+    }
+}